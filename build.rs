@@ -9,27 +9,145 @@ use std::process::Command;
 use std::str;
 
 
-/// File in the $OUT_DIR where the current revision is written.
-const REVISION_FILE: &'static str = "revision";
+/// File in the $OUT_DIR where the build-info accessor module is generated.
+const BUILD_INFO_FILE: &'static str = "build-info.rs";
+
+/// Release channel to assume when none is configured.
+const DEFAULT_CHANNEL: &'static str = "dev";
 
 
 fn main() {
-    // Obtain Git SHA to pass it further as an environment variable,
-    // so that it can be read in the binary code via env!() macro.
-    match git_head_sha() {
-        Ok(rev) => {
-            // We cannot pass it as an env!() variable to the crate code,
-            // so the workaround is to write it to a file for include_str!().
-            // Details: https://github.com/rust-lang/cargo/issues/2875
-            let out_dir = env::var("OUT_DIR").unwrap();
-            let rev_path = Path::new(&out_dir).join(REVISION_FILE);
-            File::create(&rev_path).unwrap()
-                .write_all(&rev.into_bytes()).unwrap();
-        },
-        Err(e) => println!("warning=Failed to obtain current Git SHA: {}", e),
+    // Gather a structured record of the build and generate a small typed
+    // accessor module for it (included by the `version` module). We can't pass
+    // these as env!() variables to the crate, so the workaround is to write
+    // them to a file for include!(). Details:
+    // https://github.com/rust-lang/cargo/issues/2875
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest = Path::new(&out_dir).join(BUILD_INFO_FILE);
+
+    // Resolve the revision. An explicit `ROFLD_REV` override wins, so that CI
+    // and distro packagers can stamp the exact version without Git present.
+    // Otherwise we only consult Git when there's actually a checkout to read,
+    // and fall back to "unknown" (`None`) rather than panicking or emitting a
+    // partial/garbage value for source-tarball builds.
+    let git_head = find_git_head();
+    let (commit_hash, commit_date, worktree_clean) = match revision_override() {
+        Some(rev) => (Some(rev), None, None),
+        None => {
+            if git_head.is_some() {
+                (git_head_sha().ok(), git_commit_date().ok(), git_worktree_clean())
+            } else {
+                println!("cargo:warning=No Git checkout or ROFLD_REV set; \
+                    build revision is unknown");
+                (None, None, None)
+            }
+        }
     };
+    let channel = env::var("CFG_RELEASE_CHANNEL").unwrap_or_else(|_| DEFAULT_CHANNEL.to_owned());
+
+    emit_rerun_triggers(git_head.as_ref().map(|p| p.as_path()));
+
+    let source = render_build_info(&commit_hash, &commit_date, &channel, worktree_clean);
+    File::create(&dest).unwrap()
+        .write_all(source.as_bytes()).unwrap();
+}
+
+/// Render the generated `build-info.rs` source.
+fn render_build_info(commit_hash: &Option<String>,
+                     commit_date: &Option<String>,
+                     channel: &str,
+                     worktree_clean: Option<bool>) -> String {
+    format!("\
+const COMMIT_HASH: Option<&'static str> = {};
+const COMMIT_DATE: Option<&'static str> = {};
+const CHANNEL: &'static str = {:?};
+const WORKTREE_CLEAN: Option<bool> = {};
+",
+        opt_str_literal(commit_hash),
+        opt_str_literal(commit_date),
+        channel,
+        opt_bool_literal(worktree_clean))
+}
+
+/// Format an `Option<String>` as a Rust `Option<&'static str>` literal.
+fn opt_str_literal(value: &Option<String>) -> String {
+    match *value {
+        Some(ref s) => format!("Some({:?})", s),
+        None => "None".to_owned(),
+    }
+}
+
+/// Format an `Option<bool>` as a Rust literal.
+fn opt_bool_literal(value: Option<bool>) -> String {
+    match value {
+        Some(b) => format!("Some({})", b),
+        None => "None".to_owned(),
+    }
 }
 
+/// Emit `cargo:rerun-if-*` lines so the embedded revision stays fresh after a
+/// new commit without triggering spurious rebuilds.
+fn emit_rerun_triggers(git_head: Option<&Path>) {
+    // A new commit on the checked-out branch moves both `.git/HEAD` (rarely)
+    // and the branch's ref file (every commit), so watch both.
+    if let Some(head) = git_head {
+        println!("cargo:rerun-if-changed={}", head.display());
+        if let Some(git_dir) = head.parent() {
+            if let Some(ref_name) = current_ref_name() {
+                let ref_path = git_dir.join(&ref_name);
+                println!("cargo:rerun-if-changed={}", ref_path.display());
+            }
+        }
+    }
+
+    // Re-stamp when the revision override or the release channel changes.
+    println!("cargo:rerun-if-env-changed=ROFLD_REV");
+    println!("cargo:rerun-if-env-changed=CFG_RELEASE_CHANNEL");
+}
+
+/// Resolve the symbolic full name of HEAD (e.g. `refs/heads/master`).
+fn current_ref_name() -> Option<String> {
+    let output = Command::new("git")
+        .args(&["rev-parse", "--symbolic-full-name", "HEAD"])
+        .output().ok();
+    output.and_then(|o| {
+        if o.status.success() {
+            str::from_utf8(&o.stdout[..]).ok().map(|s| s.trim().to_owned())
+                .and_then(|s| if s.is_empty() { None } else { Some(s) })
+        } else {
+            None
+        }
+    })
+}
+
+/// An explicit revision supplied via the `ROFLD_REV` environment variable.
+fn revision_override() -> Option<String> {
+    match env::var("ROFLD_REV") {
+        Ok(ref rev) if !rev.trim().is_empty() => Some(rev.trim().to_owned()),
+        _ => None,
+    }
+}
+
+/// Walk up from `CARGO_MANIFEST_DIR` looking for a `.git/HEAD` file.
+fn find_git_head() -> Option<::std::path::PathBuf> {
+    let manifest_dir = match env::var("CARGO_MANIFEST_DIR") {
+        Ok(dir) => dir,
+        Err(_) => return None,
+    };
+    let mut dir: &Path = Path::new(&manifest_dir);
+    loop {
+        let head = dir.join(".git").join("HEAD");
+        if head.exists() {
+            return Some(head);
+        }
+        match dir.parent() {
+            Some(parent) => dir = parent,
+            None => return None,
+        }
+    }
+}
+
+/// Obtain the short Git SHA of the current HEAD.
 fn git_head_sha() -> Result<String, Box<Error>> {
     let mut cmd = Command::new("git");
     cmd.args(&["rev-parse", "--short", "HEAD"]);
@@ -38,3 +156,28 @@ fn git_head_sha() -> Result<String, Box<Error>> {
     let sha = try!(str::from_utf8(&output.stdout[..])).trim().to_owned();
     Ok(sha)
 }
+
+/// Obtain the commit date of the current HEAD, formatted as YYYY-MM-DD.
+fn git_commit_date() -> Result<String, Box<Error>> {
+    let mut cmd = Command::new("git");
+    cmd.args(&["log", "-1", "--format=%cd", "--date=short"]);
+
+    let output = try!(cmd.output());
+    let date = try!(str::from_utf8(&output.stdout[..])).trim().to_owned();
+    Ok(date)
+}
+
+/// Whether the working tree is clean (no uncommitted changes).
+/// Returns `None` if Git can't be consulted.
+fn git_worktree_clean() -> Option<bool> {
+    Command::new("git")
+        .args(&["status", "--porcelain"])
+        .output().ok()
+        .and_then(|o| {
+            if o.status.success() {
+                str::from_utf8(&o.stdout[..]).ok().map(|s| s.trim().is_empty())
+            } else {
+                None
+            }
+        })
+}
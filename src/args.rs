@@ -0,0 +1,194 @@
+//! Command line argument parsing.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{App, Arg};
+
+
+/// Default address the server listens on.
+const DEFAULT_ADDRESS: &'static str = "0.0.0.0:1337";
+
+/// Default number of seconds given to in-flight requests to finish on shutdown.
+const DEFAULT_SHUTDOWN_TIMEOUT_STR: &'static str = "10";
+
+/// Default name of the external ffmpeg binary (see `resources::templates::set_ffmpeg`).
+const DEFAULT_FFMPEG: &'static str = "ffmpeg";
+
+/// Default name of the external ffprobe binary.
+const DEFAULT_FFPROBE: &'static str = "ffprobe";
+
+
+/// Kind of resource that can be preloaded into its cache at startup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    Template,
+    Font,
+}
+
+impl Resource {
+    fn from_str(s: &str) -> Option<Self> {
+        match &s.to_lowercase()[..] {
+            "template" | "templates" => Some(Resource::Template),
+            "font" | "fonts" => Some(Resource::Font),
+            _ => None,
+        }
+    }
+}
+
+
+/// Parsed command line options.
+pub struct Options {
+    pub verbosity: u64,
+    pub address: SocketAddr,
+    pub render_threads: Option<usize>,
+    pub template_cache_size: Option<usize>,
+    pub font_cache_size: Option<usize>,
+    pub preload: Vec<Resource>,
+    /// Directory templates are loaded from (see `resources::templates::set_template_dir`).
+    pub template_dir: Option<PathBuf>,
+    /// Directory fonts are loaded from (see `resources::fonts::set_font_dir`).
+    pub font_dir: Option<PathBuf>,
+    /// Whether to poll `template_dir`/`font_dir` for changes and hot-reload them.
+    pub watch_resources: bool,
+    /// Maximum allowed template dimensions (width, height), if any.
+    pub max_template_dimensions: Option<(u32, u32)>,
+    /// Maximum allowed number of frames in an animated/video template, if any.
+    pub max_template_frames: Option<usize>,
+    /// Hosts remote templates may be fetched from. Empty disables remote templates.
+    pub allowed_template_hosts: Vec<String>,
+    /// Whether to offer WebP as an output format (see `resources::templates::set_webp_enabled`).
+    pub webp: bool,
+    /// Whether video (MP4/WebM) templates are decoded at all.
+    pub video: bool,
+    /// Name/path of the ffmpeg binary used to decode & re-mux video templates.
+    pub ffmpeg: String,
+    /// Name/path of the ffprobe binary used to determine a video's frame rate.
+    pub ffprobe: String,
+    pub shutdown_timeout: Duration,
+    pub request_timeout: Duration,
+}
+
+custom_derive! {
+    #[derive(Debug, Error("argument parsing error"), ErrorDisplay, ErrorFrom)]
+    pub enum ArgsError {
+        Parse(::clap::Error),
+    }
+}
+
+
+/// Parse `Options` out of the process's command line arguments.
+pub fn parse() -> Result<Options, ArgsError> {
+    let matches = app().get_matches_safe()?;
+
+    let address = value_t!(matches, "address", SocketAddr)?;
+    let verbosity = matches.occurrences_of("verbose");
+    let render_threads = value_t!(matches, "threads", usize).ok();
+    let template_cache_size = value_t!(matches, "template-cache-size", usize).ok();
+    let font_cache_size = value_t!(matches, "font-cache-size", usize).ok();
+    let preload = matches.values_of("preload")
+        .map(|vs| vs.filter_map(Resource::from_str).collect())
+        .unwrap_or_else(Vec::new);
+    let template_dir = matches.value_of("template-dir").map(PathBuf::from);
+    let font_dir = matches.value_of("font-dir").map(PathBuf::from);
+    let watch_resources = matches.is_present("watch-resources");
+
+    // `.requires(...)` on both flags (see `app()`) already guarantees these
+    // are given together or not at all, so clap itself rejects a mismatch.
+    let max_width = value_t!(matches, "max-template-width", u32).ok();
+    let max_height = value_t!(matches, "max-template-height", u32).ok();
+    let max_template_dimensions = max_width.and_then(|w| max_height.map(|h| (w, h)));
+    let max_template_frames = value_t!(matches, "max-template-frames", usize).ok();
+
+    let allowed_template_hosts = matches.values_of("allowed-template-host")
+        .map(|vs| vs.map(str::to_owned).collect())
+        .unwrap_or_else(Vec::new);
+
+    let webp = matches.is_present("webp");
+    let video = !matches.is_present("no-video");
+    let ffmpeg = matches.value_of("ffmpeg-bin").unwrap_or(DEFAULT_FFMPEG).to_owned();
+    let ffprobe = matches.value_of("ffprobe-bin").unwrap_or(DEFAULT_FFPROBE).to_owned();
+
+    let shutdown_timeout = Duration::from_secs(
+        value_t!(matches, "shutdown-timeout", u64)?);
+    let request_timeout = Duration::from_secs(
+        value_t!(matches, "request-timeout", u64).unwrap_or(0));
+
+    Ok(Options{
+        verbosity, address, render_threads, template_cache_size, font_cache_size,
+        preload, template_dir, font_dir, watch_resources,
+        max_template_dimensions, max_template_frames, allowed_template_hosts,
+        webp, video, ffmpeg, ffprobe, shutdown_timeout, request_timeout,
+    })
+}
+
+/// Build the `clap` application describing all accepted flags.
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new(crate_name!())
+        .version(crate_version!())
+        .about("Generate and serve lulzy image macros on demand")
+        .arg(Arg::with_name("address")
+            .short("a").long("address").takes_value(true)
+            .default_value(DEFAULT_ADDRESS)
+            .help("Address to listen on"))
+        .arg(Arg::with_name("verbose")
+            .short("v").long("verbose").multiple(true)
+            .help("Increase logging verbosity (can be repeated)"))
+        .arg(Arg::with_name("threads")
+            .long("threads").takes_value(true)
+            .help("Number of threads to render image macros on [default: number of CPUs]"))
+        .arg(Arg::with_name("template-cache-size")
+            .long("template-cache-size").takes_value(true)
+            .help("Maximum number of templates to keep cached in memory"))
+        .arg(Arg::with_name("font-cache-size")
+            .long("font-cache-size").takes_value(true)
+            .help("Maximum number of fonts to keep cached in memory"))
+        .arg(Arg::with_name("preload")
+            .long("preload").takes_value(true).multiple(true)
+            .possible_values(&["template", "font"])
+            .help("Resource(s) to eagerly fill the cache with at startup"))
+        .arg(Arg::with_name("template-dir")
+            .long("template-dir").takes_value(true)
+            .help("Directory to load templates from [default: ./data/templates]"))
+        .arg(Arg::with_name("font-dir")
+            .long("font-dir").takes_value(true)
+            .help("Directory to load fonts from [default: ./data/fonts]"))
+        .arg(Arg::with_name("watch-resources")
+            .long("watch-resources")
+            .help("Watch the template/font directories and hot-reload changed files"))
+        .arg(Arg::with_name("max-template-width")
+            .long("max-template-width").takes_value(true)
+            .requires("max-template-height")
+            .help("Reject templates wider than this many pixels"))
+        .arg(Arg::with_name("max-template-height")
+            .long("max-template-height").takes_value(true)
+            .requires("max-template-width")
+            .help("Reject templates taller than this many pixels"))
+        .arg(Arg::with_name("max-template-frames")
+            .long("max-template-frames").takes_value(true)
+            .help("Reject animated/video templates with more than this many frames"))
+        .arg(Arg::with_name("allowed-template-host")
+            .long("allowed-template-host").takes_value(true).multiple(true)
+            .help("Host(s) remote (http(s)://) templates may be fetched from \
+                   [default: none, i.e. remote templates disabled]"))
+        .arg(Arg::with_name("webp")
+            .long("webp")
+            .help("Offer WebP as an output format (requires cwebp/img2webp)"))
+        .arg(Arg::with_name("no-video")
+            .long("no-video")
+            .help("Disable MP4/WebM video templates (for hosts without ffmpeg)"))
+        .arg(Arg::with_name("ffmpeg-bin")
+            .long("ffmpeg-bin").takes_value(true)
+            .help("Name or path of the ffmpeg binary [default: ffmpeg]"))
+        .arg(Arg::with_name("ffprobe-bin")
+            .long("ffprobe-bin").takes_value(true)
+            .help("Name or path of the ffprobe binary [default: ffprobe]"))
+        .arg(Arg::with_name("shutdown-timeout")
+            .long("shutdown-timeout").takes_value(true)
+            .default_value(DEFAULT_SHUTDOWN_TIMEOUT_STR)
+            .help("Seconds to wait for in-flight requests to finish on shutdown"))
+        .arg(Arg::with_name("request-timeout")
+            .long("request-timeout").takes_value(true)
+            .help("Seconds to allow a single caption request to run [default: unlimited]"))
+}
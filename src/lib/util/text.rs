@@ -2,14 +2,15 @@
 
 use std::collections::HashSet;
 use std::fmt;
+use std::mem;
 use std::ops::{Add, Div, Sub};
 
 use float_ord::FloatOrd;
 use image::{DynamicImage, GenericImage};
 use itertools::Itertools;
 use num::One;
-use regex::Regex;
-use rusttype::{GlyphId, Font, point, Point, Rect, Scale};
+use rusttype::{GlyphId, Font, point, Point, PositionedGlyph, Rect, Scale};
+use unicode_linebreak::{self, BreakOpportunity};
 use unreachable::unreachable;
 
 use model::{Color, HAlign, VAlign, DEFAULT_TEXT_SIZE};
@@ -89,11 +90,57 @@ impl Alignment {
 }
 
 
+/// How text that's too wide for its rectangle should be wrapped.
+///
+/// Mirrors fontdue's wrap hint.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapStyle {
+    /// Break between words (UAX #14), with a per-character fallback for
+    /// words longer than the line. This is the default.
+    Word,
+    /// Always break between characters -- useful for long unbroken tokens
+    /// like URLs or runs of CJK text.
+    Character,
+    /// Don't wrap at all: render each line on a single row and clip it at
+    /// the rectangle's edge.
+    None,
+}
+
+impl Default for WrapStyle {
+    #[inline]
+    fn default() -> Self { WrapStyle::Word }
+}
+
+
+/// How the text size may be adjusted to fit a rectangle.
+///
+/// Borrowed from the `pane` crate's `Resize` concept.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resize {
+    /// Shrink to fit if necessary, but never grow past the starting size.
+    NoLarger,
+    /// Pick the largest size that still fits -- growing as well as shrinking.
+    Max,
+    /// Leave the size untouched.
+    None,
+}
+
+impl Default for Resize {
+    #[inline]
+    fn default() -> Self { Resize::NoLarger }
+}
+
+
 /// Style that the text is rendered with.
 pub struct Style<'f> {
     font: &'f Font<'f>,
     size: f32,
     color: Color,
+    wrap: WrapStyle,
+    /// Optional outline drawn around the glyphs: its color and width in pixels.
+    outline: Option<(Color, f32)>,
+    /// Optional drop shadow: its color and (x, y) offset in pixels.
+    shadow: Option<(Color, (f32, f32))>,
 }
 
 impl<'f> Style<'f> {
@@ -103,7 +150,29 @@ impl<'f> Style<'f> {
         if size <= 0.0 {
             panic!("text::Style got negative size ({})", size);
         }
-        Style{font, size, color}
+        Style{font, size, color,
+              wrap: WrapStyle::default(), outline: None, shadow: None}
+    }
+
+    /// Set the wrapping style (builder-style).
+    #[inline]
+    pub fn with_wrap(mut self, wrap: WrapStyle) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Draw a `width`-pixel outline in the given color around the glyphs.
+    #[inline]
+    pub fn with_outline(mut self, color: Color, width: f32) -> Self {
+        self.outline = Some((color, width));
+        self
+    }
+
+    /// Draw a drop shadow in the given color, offset by `(dx, dy)` pixels.
+    #[inline]
+    pub fn with_shadow(mut self, color: Color, offset: (f32, f32)) -> Self {
+        self.shadow = Some((color, offset));
+        self
     }
 
     /// Get a text `Scale` corresponding to the `Style`.
@@ -125,6 +194,9 @@ impl<'f> fmt::Debug for Style<'f> {
             .field("font", &"Font{}")  // we don't have any displayable info here
             .field("size", &self.size)
             .field("color", &self.color)
+            .field("wrap", &self.wrap)
+            .field("outline", &self.outline)
+            .field("shadow", &self.shadow)
             .finish()
     }
 }
@@ -214,23 +286,72 @@ pub fn render_line<A: Into<Alignment>>(img: DynamicImage,
         },
     }
 
-    // Now we can draw the text.
-    for glyph in style.font.layout(s, scale, position) {
-        if let Some(bbox) = glyph.pixel_bounding_box() {
-            glyph.draw(|x, y, v| {
-                let x = (bbox.min.x + x as i32) as u32;
-                let y = (bbox.min.y + y as i32) as u32;
-                let alpha = (v * 255f32) as u8;
-                if img.in_bounds(x, y) {
-                    img.blend_pixel(x, y, style.color.to_rgba(alpha));
+    // Lay out the glyphs once; we may paint them in several passes
+    // (shadow, outline, fill) so they have to be reusable.
+    let glyphs: Vec<_> = style.font.layout(s, scale, position).collect();
+
+    // The drop shadow goes underneath everything, offset by a fixed amount.
+    if let Some((color, (dx, dy))) = style.shadow {
+        for glyph in &glyphs {
+            blend_glyph(&mut img, glyph, dx, dy, color, rect);
+        }
+    }
+
+    // The outline is the glyph coverage dilated by the outline radius: we
+    // paint the outline color at every integer offset within a disk of that
+    // radius, then let the fill (painted last) cover the core.
+    if let Some((color, width)) = style.outline {
+        let radius = width.round() as i32;
+        for glyph in &glyphs {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if (dx == 0 && dy == 0) || dx * dx + dy * dy > radius * radius {
+                        continue;
+                    }
+                    blend_glyph(&mut img, glyph, dx as f32, dy as f32, color, rect);
                 }
-            });
+            }
         }
     }
 
+    // Finally the fill, on top of the shadow and outline.
+    for glyph in &glyphs {
+        blend_glyph(&mut img, glyph, 0.0, 0.0, style.color, rect);
+    }
+
     img
 }
 
+/// Alpha-blend a single positioned glyph onto the image at an integer pixel
+/// offset, using the glyph's coverage as the alpha for the given color.
+///
+/// Pixels falling outside `rect` are skipped, so that text rendered with
+/// `WrapStyle::None` is clipped at the rectangle's edge as documented,
+/// rather than overflowing into the rest of the image.
+fn blend_glyph<'a>(img: &mut DynamicImage,
+                   glyph: &PositionedGlyph<'a>,
+                   dx: f32, dy: f32, color: Color, rect: Rect<f32>) {
+    if let Some(bbox) = glyph.pixel_bounding_box() {
+        let (dx, dy) = (dx.round() as i32, dy.round() as i32);
+        glyph.draw(|x, y, v| {
+            let px = bbox.min.x + x as i32 + dx;
+            let py = bbox.min.y + y as i32 + dy;
+            if px < 0 || py < 0 {
+                return;
+            }
+            if (px as f32) < rect.min.x || (px as f32) >= rect.max.x
+                || (py as f32) < rect.min.y || (py as f32) >= rect.max.y {
+                return;
+            }
+            let (px, py) = (px as u32, py as u32);
+            let alpha = (v * 255f32) as u8;
+            if img.in_bounds(px, py) {
+                img.blend_pixel(px, py, color.to_rgba(alpha));
+            }
+        });
+    }
+}
+
 
 /// Return the maximum text size that'd still allow us to fit the text
 /// within given rectangle.
@@ -238,55 +359,29 @@ pub fn render_line<A: Into<Alignment>>(img: DynamicImage,
 /// The size returned may be ridiculous if the text is long enough
 /// (or the rectangle is small enough). However, if the size cannot be determined
 /// in reasonable number of iterations, None is returned.
-pub fn fit_text<'s, 'f>(rect: Rect<f32>, s: &'s str, font: &'f Font<'f>) -> Option<f32> {
-    trace!("fit_text({:?}, <{} bytes of text>, ...", rect, s.len());
+///
+/// `resize` selects whether the starting size is only ever shrunk
+/// (`Resize::NoLarger`), may also grow to fill the rectangle (`Resize::Max`),
+/// or is left untouched (`Resize::None`).
+pub fn fit_text<'s, 'f>(rect: Rect<f32>, s: &'s str, font: &'f Font<'f>,
+                        resize: Resize) -> Option<f32> {
+    trace!("fit_text({:?}, <{} bytes of text>, {:?})", rect, s.len(), resize);
     if rect.width() <= 0.0 || rect.height() <= 0.0 {
         return None;
     }
 
-    // TODO: pick a larger default size so that short texts will
-    // still completely fill larger rectangles
-    let mut size = DEFAULT_TEXT_SIZE;
     let unused_color = Color::white();  // not used, but needed for Style
-
-    // Gradually shrink the text, break it into lines,
-    // and try to fit it within the given rectangle.
-    ///
-    // Continue to do so until succeeded,
-    // or a maximum number of iterations has been reached.
-    const SHRINK_FACTOR: f32 = 0.9;
-    const MAX_ITERS: usize = 16;
-    let mut iters = 1;
-    while iters <= MAX_ITERS {
+    let fits = |size: f32| {
         let style = Style::new(font, size, unused_color);
         let lines = break_lines(s, &style, rect.width());
-
         let width = lines.iter().map(|line| text_width(line, &style))
             .map(FloatOrd).max().map(|w| w.0)
             .unwrap_or(0.0);
         let height = lines.len() as f32 * style.line_height();
-        if width <= rect.width() && height <= rect.height() {
-            break;  // Found a fitting size.
-        }
-
-        let new_size = size * SHRINK_FACTOR;
-        if new_size >= size {
-            // Seems we got REALLY small and float inaccuracies started to matter.
-            warn!("Text size lost accuracy ({:?}) after {} iterations, starting from size {}",
-                new_size, iters, DEFAULT_TEXT_SIZE);
-            return None;
-        }
-        size = new_size;
-        iters += 1;
-    }
+        width <= rect.width() && height <= rect.height()
+    };
 
-    if iters > MAX_ITERS {
-        warn!(
-            "Couldn't fit text in a {}x{} rect even after {} iterations (last attempt: {})",
-            rect.width(), rect.height(), MAX_ITERS, size);
-        return None;
-    }
-    Some(size)
+    fit_size(DEFAULT_TEXT_SIZE, resize, &fits)
 }
 
 /// Return the maximum text size that'd still allow us to fit a line
@@ -298,41 +393,75 @@ pub fn fit_text<'s, 'f>(rect: Rect<f32>, s: &'s str, font: &'f Font<'f>) -> Opti
 ///
 /// This should only be called on single-line texts.
 /// Any preexisting line break characters will be ignored.
-pub fn fit_line<'s, 'f>(max_width: f32, s: &'s str, font: &'f Font<'f>) -> Option<f32> {
-    trace!("fit_line({:?}, <{} bytes of text>, ...)", max_width, s.len());
+///
+/// See `fit_text` for what `resize` selects.
+pub fn fit_line<'s, 'f>(max_width: f32, s: &'s str, font: &'f Font<'f>,
+                        resize: Resize) -> Option<f32> {
+    trace!("fit_line({:?}, <{} bytes of text>, {:?})", max_width, s.len(), resize);
     if max_width <= 0.0 {
         return None;
     }
 
-    // TODO: pick a larger default size so that short texts will
-    // still completely fill larger rectangles
-    let mut size = DEFAULT_TEXT_SIZE;
     let color = Color::white();  // not used, but needed for Style
+    let fits = |size: f32| text_width(s, &Style::new(font, size, color)) <= max_width;
 
-    // Gradually shrink the size and try to fit it,
-    // but prevent infinite loops if we can't fit it after all.
-    const MAX_ITERS: usize = 16;
-    let mut iters = 1;
-    while iters <= MAX_ITERS && text_width(s, &Style::new(font, size, color)) > max_width {
-        const SHRINK_FACTOR: f32 = 0.9;
-        let new_size = size * SHRINK_FACTOR;
-        if new_size >= size {
-            // Seems we got REALLY small and float inaccuracies started to matter.
-            warn!("Text size lost accuracy ({:?}) after {} iterations, starting from {}",
-                new_size, iters, DEFAULT_TEXT_SIZE);
-            return None;
-        }
-        size = new_size;
-        iters += 1;
+    fit_size(DEFAULT_TEXT_SIZE, resize, &fits)
+}
+
+
+/// Find the largest font size satisfying `fits`, by bisection.
+///
+/// The `start` size only matters for `Resize::None` (returned verbatim) and as
+/// the upper cap for `Resize::NoLarger`. A low bound known to fit is taken as
+/// the minimum size, the high bound is grown by doubling until it no longer
+/// fits (capped), and the largest fitting size is then bisected out. This
+/// converges in logarithmically many iterations and never spuriously fails on
+/// text that is actually fittable.
+fn fit_size<F: Fn(f32) -> bool>(start: f32, resize: Resize, fits: &F) -> Option<f32> {
+    /// Smallest size we ever consider; assumed to fit.
+    const MIN_SIZE: f32 = 1.0;
+    /// Largest size we'll ever grow to.
+    const MAX_SIZE: f32 = 4096.0;
+    /// Stop once the search window is narrower than this, in pixels.
+    const EPSILON: f32 = 0.5;
+
+    if let Resize::None = resize {
+        return Some(start);
     }
 
-    if iters > MAX_ITERS {
-        warn!(
-            "Couldn't fit text in a width of {} even after {} iterations (last attempt: {})",
-            max_width, MAX_ITERS, size);
+    // Low bound that is known to fit.
+    let mut lo = MIN_SIZE;
+    if !fits(lo) {
+        warn!("Text doesn't fit even at the minimum size of {}", MIN_SIZE);
         return None;
     }
-    Some(size)
+
+    // Upper cap on the size: the starting size unless we're allowed to grow.
+    let cap = match resize {
+        Resize::NoLarger => start,
+        _ => MAX_SIZE,
+    };
+    if lo >= cap {
+        return Some(lo);
+    }
+
+    // Grow the high bound by doubling until it no longer fits (or hits the cap).
+    let mut hi = (lo * 2.0).min(cap);
+    while fits(hi) {
+        if hi >= cap {
+            // Everything up to the cap fits, so the cap is the answer.
+            return Some(cap);
+        }
+        lo = hi;
+        hi = (hi * 2.0).min(cap);
+    }
+
+    // Binary-search the largest fitting size within (lo, hi], returning `lo`.
+    while hi - lo > EPSILON {
+        let mid = (lo + hi) / 2.0;
+        if fits(mid) { lo = mid; } else { hi = mid; }
+    }
+    Some(lo)
 }
 
 
@@ -366,101 +495,96 @@ fn char_width(c: char, style: &Style) -> f32 {
 // Line breaking.
 
 /// Break the text into lines, fitting given width.
+///
+/// The wrapping behavior is governed by the style's `WrapStyle`.
 fn break_lines(s: &str, style: &Style, line_width: f32) -> Vec<String> {
-    s.lines()
-        .flat_map(|line| break_single_line(line, style, line_width))
-        .collect()
+    match style.wrap {
+        WrapStyle::Word => s.lines()
+            .flat_map(|line| break_single_line(line, style, line_width))
+            .collect(),
+        WrapStyle::Character => s.lines()
+            .flat_map(|line| break_line_by_char(line, style, line_width))
+            .collect(),
+        // No wrapping: keep each input line on its own row (clipped at render).
+        WrapStyle::None => s.lines().map(|line| line.to_owned()).collect(),
+    }
+}
+
+/// Break a single line purely between characters (greedy).
+fn break_line_by_char(s: &str, style: &Style, line_width: f32) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current_line = String::new();
+    let mut current_width = 0.0;
+    hard_break_segment(s, &|c| char_width(c, style), line_width,
+        &mut result, &mut current_line, &mut current_width);
+    if !current_line.is_empty() {
+        result.push(current_line);
+    }
+    result
 }
 
 /// Break a single line into multiple lines.
 /// The line should not contain explicit line breaks.
+///
+/// Break opportunities are determined by the Unicode Line Breaking Algorithm
+/// (UAX #14) rather than a simple ASCII word boundary, so the filler can break
+/// after hyphens and em-dashes, between CJK ideographs, and at the many other
+/// opportunities the algorithm defines, while never splitting a combining mark
+/// from its base. The greedy filler accumulates glyph widths and breaks at the
+/// last allowed opportunity that still fits.
 fn break_single_line(s: &str, style: &Style, line_width: f32) -> Vec<String> {
-    lazy_static! {
-        static ref WORD_BOUNDARY: Regex = Regex::new(r"\b").unwrap();
+    // Segment the text at UAX #14 break opportunities. Each segment ends at an
+    // opportunity and carries whether the break after it is mandatory.
+    let mut segments: Vec<(&str, bool)> = Vec::new();
+    let mut prev = 0;
+    for (idx, opportunity) in unicode_linebreak::linebreaks(s) {
+        let mandatory = opportunity == BreakOpportunity::Mandatory;
+        segments.push((&s[prev..idx], mandatory));
+        prev = idx;
     }
-
-    let segments: Vec<&str> = WORD_BOUNDARY.split(s).filter(|s| !s.is_empty()).collect();
-    let is_word = |s: &str| s.chars().all(|c| !c.is_whitespace());
-    trace!("Computing line breaks for text of length {} with {} word(s) and {} gap(s)",
-        s.len(),
-        segments.iter().map(|s| is_word(s)).count(),
-        segments.iter().map(|s| !is_word(s)).count());
+    trace!("Computing line breaks for text of length {} with {} break opportunity(ies)",
+        s.len(), segments.len());
 
     let mut result = Vec::with_capacity(segments.len() / 2 /* a guess */);
 
     let mut current_line = String::new();
     let mut current_width = 0.0;
-    for segment in segments {
-        let mut segment_width = text_width(segment, style);
+    for (segment, mandatory) in segments {
+        let segment_width = text_width(segment, style);
 
         // Simplest case is when the segment trivially fits within the line.
         if current_width + segment_width < line_width {
             current_line.push_str(segment);
             current_width += segment_width;
-            continue;
         }
-
         // If the segment doesn't fit, but it is not longer than the line by itself,
         // break the current line before it & put the segment in the next one.
-        if segment_width < line_width {
+        else if segment_width < line_width {
             if !current_line.is_empty() {
-                result.push(current_line);
+                result.push(mem::replace(&mut current_line, String::new()));
             }
-            // If the overflowing segment is just a single space,
-            // then just forget about it completely.
-            // That space is adequately represented by the line break itself.
-            if segment == " " {
-                current_line = String::new();
+            // A run of spaces before a break is collapsed into the break itself,
+            // so a segment of pure whitespace is dropped when it would start a line.
+            if segment.chars().all(|c| c.is_whitespace()) {
                 current_width = 0.0;
             } else {
                 current_line = segment.to_owned();
                 current_width = segment_width;
             }
-            continue;
+        }
+        // The worst case scenario is that the segment itself is longer than the
+        // line, with no internal break opportunity. We fall back to breaking it
+        // between characters (possibly multiple times).
+        else {
+            hard_break_segment(segment, &|c| char_width(c, style), line_width,
+                &mut result, &mut current_line, &mut current_width);
         }
 
-        // The worst case scenario is that the segment itself is longer than the line.
-        // In this case, we have to break it up (possibly multiple times).
-        let mut segment = segment.to_owned();
-        loop {
-            // Break it at the earliest possible spot by shaving off characters
-            // from the end. Remember what part of the segment shall carry over
-            // to the next line, too.
-            let mut carryover: Vec<char> = vec![];
-            let mut carryover_width = 0.0;
-            while current_width + segment_width > line_width {
-                match segment.pop() {
-                    Some(c) => {
-                        carryover.push(c);
-                        let ch_width = char_width(c, style);
-                        segment_width -= ch_width;
-                        carryover_width += ch_width;
-                    },
-                    None => {
-                        segment_width = 0.0;
-                        break;
-                    },
-                }
-            }
-
-            // What remains will fit within the current line now,
-            // so we just add it in there.
-            // And if there is nothing to carry over, we're done.
-            current_line.push_str(&segment);
-            current_width += segment_width;
-            if carryover.is_empty() {
-                break;
-            }
-
-            // Otherwise, we need to start a new line for the carryover part...
-            result.push(current_line);
-            current_line = String::new();
+        // Honor a mandatory break (e.g. a line/paragraph separator) after the
+        // segment by flushing the current line.
+        if mandatory && !current_line.is_empty() {
+            result.push(mem::replace(&mut current_line, String::new()));
             current_width = 0.0;
-
-            // ...which now also becomes the new segment part,
-            // ready to be broken up in an identical way.
-            segment = carryover.into_iter().rev().collect();
-            segment_width = carryover_width;
         }
     }
     if !current_line.is_empty() {
@@ -469,3 +593,129 @@ fn break_single_line(s: &str, style: &Style, line_width: f32) -> Vec<String> {
 
     result
 }
+
+/// Break a single over-long segment between characters, appending the
+/// resulting lines to `result` and leaving the unfinished remainder in
+/// `current_line`.
+///
+/// Takes a `char_width` function rather than a `Style` directly so it can be
+/// unit-tested against a synthetic width function, without needing a real
+/// `Font` to back a `Style`.
+fn hard_break_segment<F: Fn(char) -> f32>(segment: &str, char_width: &F, line_width: f32,
+                      result: &mut Vec<String>,
+                      current_line: &mut String, current_width: &mut f32) {
+    let mut segment = segment.to_owned();
+    let mut segment_width: f32 = segment.chars().map(char_width).sum();
+    loop {
+        // Break it at the earliest possible spot by shaving off characters
+        // from the end. Remember what part of the segment shall carry over
+        // to the next line, too.
+        let mut carryover: Vec<char> = vec![];
+        let mut carryover_width = 0.0;
+        while *current_width + segment_width > line_width {
+            match segment.pop() {
+                Some(c) => {
+                    carryover.push(c);
+                    let ch_width = char_width(c);
+                    segment_width -= ch_width;
+                    carryover_width += ch_width;
+                },
+                None => {
+                    segment_width = 0.0;
+                    break;
+                },
+            }
+        }
+
+        // What remains will fit within the current line now,
+        // so we just add it in there.
+        current_line.push_str(&segment);
+        *current_width += segment_width;
+
+        // If that left the current line empty but there's still carryover,
+        // not even a single character of it fits within line_width. Force
+        // the first one through unconditionally (it's the last char pushed
+        // onto carryover, since we popped from the end) so each outer
+        // iteration strictly shrinks the remaining text, rather than
+        // re-carrying the exact same content onto a fresh, equally narrow
+        // line forever.
+        if current_line.is_empty() {
+            if let Some(c) = carryover.pop() {
+                let ch_width = char_width(c);
+                current_line.push(c);
+                *current_width += ch_width;
+                carryover_width -= ch_width;
+            }
+        }
+
+        // And if there is nothing left to carry over, we're done.
+        if carryover.is_empty() {
+            break;
+        }
+
+        // Otherwise, we need to start a new line for the carryover part...
+        result.push(mem::replace(current_line, String::new()));
+        *current_width = 0.0;
+
+        // ...which now also becomes the new segment part,
+        // ready to be broken up in an identical way.
+        segment = carryover.into_iter().rev().collect();
+        segment_width = carryover_width;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{fit_size, hard_break_segment, Resize};
+
+    #[test]
+    fn resize_none_returns_the_start_size() {
+        let always = |_: f32| true;
+        assert_eq!(fit_size(17.0, Resize::None, &always), Some(17.0));
+    }
+
+    #[test]
+    fn no_larger_never_grows_past_the_start_size() {
+        let always = |_: f32| true;
+        assert_eq!(fit_size(24.0, Resize::NoLarger, &always), Some(24.0));
+    }
+
+    #[test]
+    fn no_larger_shrinks_to_the_largest_fitting_size() {
+        // Only sizes up to 10 fit, so starting from 40 we must shrink to ~10.
+        let fits = |s: f32| s <= 10.0;
+        let size = fit_size(40.0, Resize::NoLarger, &fits).unwrap();
+        assert!((size - 10.0).abs() <= 0.5, "got {}", size);
+    }
+
+    #[test]
+    fn max_grows_beyond_the_start_size() {
+        let fits = |s: f32| s <= 100.0;
+        let size = fit_size(12.0, Resize::Max, &fits).unwrap();
+        assert!((size - 100.0).abs() <= 0.5, "got {}", size);
+    }
+
+    #[test]
+    fn returns_none_when_nothing_fits() {
+        let never = |_: f32| false;
+        assert_eq!(fit_size(20.0, Resize::Max, &never), None);
+    }
+
+    #[test]
+    fn hard_break_segment_terminates_when_a_single_char_is_wider_than_the_line() {
+        // Every character is wider than line_width on its own, so the only
+        // possible progress is one forced character per line. If this
+        // regresses to the pre-fix behavior, the loop never returns.
+        let char_width = |_: char| 10.0;
+        let mut result = Vec::new();
+        let mut current_line = String::new();
+        let mut current_width = 0.0;
+        hard_break_segment("ABC", &char_width, 5.0,
+            &mut result, &mut current_line, &mut current_width);
+        if !current_line.is_empty() {
+            result.push(current_line);
+        }
+        assert_eq!(result, vec!["A".to_owned(), "B".to_owned(), "C".to_owned()]);
+    }
+}
@@ -56,9 +56,9 @@ mod logging;
 mod model;
 mod resources;
 mod service;
+mod version;
 
 
-use std::error::Error;
 use std::env;
 use std::io::{self, Write};
 use std::process::exit;
@@ -74,16 +74,6 @@ use caption::CAPTIONER;
 lazy_static! {
     /// Application / package name, as filled out by Cargo.
     static ref NAME: &'static str = option_env!("CARGO_PKG_NAME").unwrap_or("rofld");
-
-    /// Application version, as filled out by Cargo.
-    static ref VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
-
-    /// Application revision, such as Git SHA.
-    /// This is generated by a build script and written to an output file.
-    static ref REVISION: Option<&'static str> = {
-        let revision = include_str!(concat!(env!("OUT_DIR"), "/", "revision"));
-        if revision.trim().is_empty() { None } else { Some(revision) }
-    };
 }
 
 
@@ -94,9 +84,7 @@ fn main() {
     });
 
     logging::init(opts.verbosity).unwrap();
-    info!("{} {}{}", *NAME,
-        VERSION.map(|v| format!("v{}", v)).unwrap_or_else(|| "<UNKNOWN VERSION>".into()),
-        REVISION.map(|r| format!(" (rev. {})", r)).unwrap_or_else(|| "".into()));
+    info!("{} {}", *NAME, version::build_info());
     if let Some(pid) = get_process_id() {
         debug!("PID = {}", pid);
     }
@@ -109,19 +97,9 @@ fn main() {
 
 /// Print an error that may occur while parsing arguments.
 fn print_args_error(e: ArgsError) -> io::Result<()> {
-    match e {
-        ArgsError::Parse(ref e) =>
-            // In case of generic parse error,
-            // message provided by the clap library will be the usage string.
-            writeln!(&mut io::stderr(), "{}", e.message),
-        e => {
-            let mut msg = "Failed to parse arguments".to_owned();
-            if let Some(cause) = e.cause() {
-                msg += &format!(": {}", cause);
-            }
-            writeln!(&mut io::stderr(), "{}", msg)
-        },
-    }
+    let ArgsError::Parse(e) = e;
+    // Message provided by the clap library will already be the usage string.
+    writeln!(&mut io::stderr(), "{}", e.message)
 }
 
 #[cfg(unix)]
@@ -174,6 +152,49 @@ fn set_config<S, B>(opts: Options, server: &mut Server<S, B>)
         CAPTIONER.cache().set_font_capacity(fcs);
         debug!("Size of the font cache set to {}", fcs);
     }
+    if let Some(ref dir) = opts.template_dir {
+        resources::templates::set_template_dir(dir.clone());
+        debug!("Template directory set to {}", dir.display());
+    }
+    if let Some(ref dir) = opts.font_dir {
+        resources::fonts::set_font_dir(dir.clone());
+        debug!("Font directory set to {}", dir.display());
+    }
+    if opts.watch_resources {
+        let interval = ::std::time::Duration::from_secs(2);
+        debug!("Watching template/font directories for changes every {}s",
+            interval.as_secs());
+        resources::templates::watch_changes(interval, |name| {
+            debug!("Reloading changed template `{}`", name);
+            CAPTIONER.cache().invalidate_template(name);
+        });
+        resources::fonts::watch_changes(interval, |name| {
+            debug!("Reloading changed font `{}`", name);
+            CAPTIONER.cache().invalidate_font(name);
+        });
+    }
+    if let Some((max_w, max_h)) = opts.max_template_dimensions {
+        resources::templates::set_max_dimensions(max_w, max_h);
+        debug!("Maximum template dimensions set to {}x{}", max_w, max_h);
+    }
+    if let Some(max_frames) = opts.max_template_frames {
+        resources::templates::set_max_frames(max_frames);
+        debug!("Maximum template frame count set to {}", max_frames);
+    }
+    if !opts.allowed_template_hosts.is_empty() {
+        resources::templates::set_allowed_hosts(opts.allowed_template_hosts.iter().cloned());
+        debug!("Remote templates allowed from {} host(s)",
+            opts.allowed_template_hosts.len());
+    }
+    // Preload only after the directories/limits above are in place, so it
+    // warms the cache from the configured locations under the configured
+    // ingestion limits rather than whatever the defaults happen to be.
+    for resource in &opts.preload {
+        CAPTIONER.preload(*resource);
+    }
+    resources::templates::set_webp_enabled(opts.webp);
+    debug!("WebP output {}", if opts.webp { "enabled" } else { "disabled" });
+    resources::templates::set_ffmpeg(opts.video, opts.ffmpeg.clone(), opts.ffprobe.clone());
     server.shutdown_timeout(opts.shutdown_timeout);
     debug!("Shutdown timeout set to {} secs", opts.shutdown_timeout.as_secs());
     CAPTIONER.set_task_timeout(opts.request_timeout);
@@ -0,0 +1,79 @@
+//! Module handling caption fonts.
+
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use antidote::Mutex;
+
+
+lazy_static! {
+    /// Directory the fonts are loaded from. Defaults to `$CWD/data/fonts` but
+    /// can be overridden at runtime via the `--font-dir` flag (see
+    /// `set_font_dir`).
+    static ref FONT_DIR: Mutex<PathBuf> = Mutex::new(
+        env::current_dir().unwrap().join("data").join("fonts"));
+}
+
+/// Override the directory that fonts are loaded from.
+pub fn set_font_dir<P: Into<PathBuf>>(path: P) {
+    let path = path.into();
+    debug!("Font directory set to {}", path.display());
+    *FONT_DIR.lock() = path;
+}
+
+/// The directory fonts are currently loaded from.
+pub fn font_dir() -> PathBuf {
+    FONT_DIR.lock().clone()
+}
+
+/// Watch the font directory and invoke `on_change` with a font name whenever
+/// its backing file is created, modified, or removed.
+///
+/// Like the template watcher, this spawns a background polling thread so that
+/// replacing a font on disk takes effect without restarting the process; the
+/// captioner uses the callback to drop the affected entry from the font cache.
+pub fn watch_changes<F>(interval: Duration, on_change: F)
+    where F: Fn(&str) + Send + 'static
+{
+    use std::collections::HashMap;
+    use std::fs;
+    use std::thread;
+    use std::time::SystemTime;
+
+    thread::spawn(move || {
+        let mut mtimes: HashMap<String, SystemTime> = HashMap::new();
+        let mut first = true;
+        loop {
+            let mut seen = HashMap::new();
+            if let Ok(entries) = fs::read_dir(font_dir()) {
+                for entry in entries.filter_map(Result::ok) {
+                    let path = entry.path();
+                    let name = match path.file_stem().and_then(|s| s.to_str()) {
+                        Some(n) => n.to_owned(),
+                        None => continue,
+                    };
+                    let mtime = entry.metadata().and_then(|m| m.modified()).ok();
+                    if let Some(mtime) = mtime {
+                        if !first && mtimes.get(&name) != Some(&mtime) {
+                            trace!("Detected change to font `{}`", name);
+                            on_change(&name);
+                        }
+                        seen.insert(name, mtime);
+                    }
+                }
+            }
+            if !first {
+                for name in mtimes.keys() {
+                    if !seen.contains_key(name) {
+                        trace!("Font `{}` was removed", name);
+                        on_change(name);
+                    }
+                }
+            }
+            mtimes = seen;
+            first = false;
+            thread::sleep(interval);
+        }
+    });
+}
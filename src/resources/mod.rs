@@ -0,0 +1,4 @@
+//! Module handling on-disk resources: templates and fonts.
+
+pub mod fonts;
+pub mod templates;
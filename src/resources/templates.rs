@@ -1,12 +1,18 @@
 //! Module handling image macro templates.
 
+use std::cell::RefCell;
 use std::env;
 use std::fmt;
+use std::io::{self, Write};
 use std::iter;
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
 
+use antidote::Mutex;
 use conv::TryFrom;
 use glob;
+use hyper::{self, StatusCode};
 use image::{self, DynamicImage, GenericImage, ImageFormat};
 
 use util::animated_gif::{self, GifAnimation, is_gif, is_gif_animated};
@@ -15,6 +21,66 @@ use util::animated_gif::{self, GifAnimation, is_gif, is_gif_animated};
 /// Default image format to use when encoding image macros.
 pub const DEFAULT_IMAGE_FORMAT: ImageFormat = ImageFormat::PNG;
 
+/// Default path to the external still-image WebP encoder (`libwebp`'s `cwebp`).
+/// Still images are always encoded with this binary (see `encode_webp`);
+/// there is no setting for a different path.
+pub const DEFAULT_WEBP_ENCODER: &'static str = "cwebp";
+
+/// Default path to the external *animated* WebP encoder. `cwebp` only handles
+/// single still images, so animations go through `img2webp` (part of the same
+/// `libwebp` tools) instead.
+pub const DEFAULT_WEBP_ANIM_ENCODER: &'static str = "img2webp";
+
+
+/// Container format of a video template.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoContainer {
+    Mp4,
+    WebM,
+}
+
+impl VideoContainer {
+    /// Detect the container from a file extension, if recognized.
+    fn from_extension(ext: &str) -> Option<Self> {
+        match &ext.to_lowercase()[..] {
+            "mp4" => Some(VideoContainer::Mp4),
+            "webm" => Some(VideoContainer::WebM),
+            _ => None,
+        }
+    }
+
+    /// File extension (and ffmpeg muxer name) for this container.
+    fn extension(&self) -> &'static str {
+        match *self { VideoContainer::Mp4 => "mp4", VideoContainer::WebM => "webm" }
+    }
+}
+
+/// A short video clip decoded into its constituent frames.
+///
+/// Decoding is done out-of-process via `ffmpeg` (see `decode_video`), so this
+/// merely holds the extracted frames together with the source frame rate and
+/// the original container, which are needed to re-mux after captioning.
+#[derive(Clone)]
+pub struct VideoClip {
+    frames: Vec<DynamicImage>,
+    fps: f64,
+    container: VideoContainer,
+}
+
+impl VideoClip {
+    /// Number of extracted frames.
+    #[inline]
+    pub fn frames_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Iterate over the clip's frames.
+    #[inline]
+    pub fn iter_frames<'c>(&'c self) -> ::std::slice::Iter<'c, DynamicImage> {
+        self.frames.iter()
+    }
+}
+
 
 /// Represents an image macro template.
 #[derive(Clone)]
@@ -23,6 +89,8 @@ pub enum Template {
     Image(DynamicImage, ImageFormat),
     /// An animation, loaded from a GIF.
     Animation(GifAnimation),
+    /// A short video clip, decoded from an MP4 or WebM container.
+    Video(VideoClip),
 }
 
 impl Template {
@@ -36,6 +104,7 @@ impl Template {
                 "jpg" | "jpeg" => Some(ImageFormat::JPEG),
                 "png" => Some(ImageFormat::PNG),
                 "gif" => Some(ImageFormat::GIF),
+                "webp" => Some(ImageFormat::WEBP),
                 _ => None,
             }
         }).unwrap_or(DEFAULT_IMAGE_FORMAT);
@@ -47,13 +116,21 @@ impl Template {
     pub fn for_gif_animation(gif_anim: GifAnimation) -> Self {
         Template::Animation(gif_anim)
     }
+
+    #[inline]
+    pub fn for_video(clip: VideoClip) -> Self {
+        Template::Video(clip)
+    }
 }
 
 impl Template {
     /// Whether this is an animated template.
     #[inline]
     pub fn is_animated(&self) -> bool {
-        match *self { Template::Animation(..) => true, _ => false, }
+        match *self {
+            Template::Animation(..) | Template::Video(..) => true,
+            _ => false,
+        }
     }
 
     /// Number of images that comprise the template
@@ -62,6 +139,7 @@ impl Template {
         match *self {
             Template::Image(..) => 1,
             Template::Animation(ref gif_anim) => gif_anim.frames_count(),
+            Template::Video(ref clip) => clip.frames_count(),
         }
     }
 
@@ -71,6 +149,7 @@ impl Template {
             Template::Image(ref img, ..) => Box::new(iter::once(img)),
             Template::Animation(ref gif_anim) => Box::new(
                 gif_anim.iter_frames().map(|f| &f.image)),
+            Template::Video(ref clip) => Box::new(clip.iter_frames()),
         }
     }
 
@@ -81,14 +160,219 @@ impl Template {
             Template::Image(_, fmt) => match fmt {
                 // These are the formats that image crate encodes natively.
                 ImageFormat::PNG | ImageFormat::JPEG => return fmt,
+                // WebP isn't encoded natively, but we can round-trip it
+                // through the external encoder (see `encode_webp`) -- but only
+                // when the feature is actually enabled. Otherwise fall through
+                // to the default still format so output never silently breaks.
+                ImageFormat::WEBP if webp_enabled() => return fmt,
                 _ => {}
             },
             Template::Animation(..) => return ImageFormat::GIF,
+            // Video clips are re-muxed by ffmpeg rather than encoded by the
+            // image crate; we still report GIF as the closest still-image
+            // fallback for callers that can't deal with a video container.
+            Template::Video(..) => return ImageFormat::GIF,
         }
         DEFAULT_IMAGE_FORMAT
     }
 }
 
+/// WebP output feature flag, set through `set_webp_enabled`. Off by default so
+/// that installations without the `libwebp` tools keep their previous behavior
+/// (templates loaded from WebP fall back to a natively-encoded format).
+///
+/// This belongs on `EngineConfig` alongside `jpeg_quality`/`gif_quality`
+/// rather than as a process-global static, but moving it there depends on
+/// `caption::engine`, which isn't part of this checkout; tracked for when
+/// that module lands.
+static WEBP_ENABLED: ::std::sync::atomic::AtomicBool =
+    ::std::sync::atomic::ATOMIC_BOOL_INIT;
+
+/// Enable or disable WebP output at runtime (see `set_config`).
+pub fn set_webp_enabled(enabled: bool) {
+    WEBP_ENABLED.store(enabled, ::std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Whether WebP output is currently enabled.
+fn webp_enabled() -> bool {
+    WEBP_ENABLED.load(::std::sync::atomic::Ordering::Relaxed)
+}
+
+// WebP encoding via an external binary.
+//
+// The bundled `image` crate can only decode WebP, so -- following the way
+// pict-rs shells WebP encoding out to `libwebp`'s command line tools -- we
+// write the decoded frames to a scratch directory and run `cwebp` (still
+// images) or `img2webp` (animations) over them. The feature is opt-in via
+// `set_webp_enabled`; while it is off, `preferred_format()` never picks WebP.
+impl Template {
+    /// Encode this template as WebP bytes, paired with the MIME type to serve
+    /// them under. This is the entry point the caption output layer calls when
+    /// `preferred_format()` selects WebP (only while `set_webp_enabled` is on).
+    pub fn encode_webp_output(&self, quality: u8) -> io::Result<(Vec<u8>, &'static str)> {
+        let bytes = self.encode_webp(quality)?;
+        Ok((bytes, media_type(ImageFormat::WEBP)))
+    }
+
+    /// Encode this template as WebP bytes.
+    ///
+    /// `quality` is passed through verbatim as the encoder's `-q` parameter
+    /// (0-100). Still images go through `cwebp`; animations and video clips are
+    /// encoded as *animated* WebP with `img2webp`, since `cwebp` cannot encode
+    /// more than a single frame. Per-frame delays come from the source timing.
+    pub fn encode_webp(&self, quality: u8) -> io::Result<Vec<u8>> {
+        match *self {
+            Template::Image(ref img, ..) =>
+                encode_webp_still(DEFAULT_WEBP_ENCODER, img, quality),
+            Template::Animation(ref gif_anim) =>
+                encode_webp_animation(DEFAULT_WEBP_ANIM_ENCODER, gif_anim, quality),
+            // A captioned video clip becomes an animated WebP, spacing the
+            // frames evenly according to its source frame rate.
+            Template::Video(ref clip) => {
+                let delay_ms = if clip.fps > 0.0 {
+                    (1000.0 / clip.fps).round() as u32
+                } else {
+                    0
+                };
+                encode_webp_frames(DEFAULT_WEBP_ANIM_ENCODER,
+                    clip.iter_frames(), delay_ms, quality)
+            }
+        }
+    }
+}
+
+/// Encode a single still image as WebP through `cwebp`.
+fn encode_webp_still(encoder: &str, img: &DynamicImage, quality: u8) -> io::Result<Vec<u8>> {
+    let dir = scratch_dir()?;
+    let src = dir.join("frame.png");
+    let dst = dir.join("out.webp");
+    img.save(&src).map_err(to_io_error)?;
+
+    run_encoder(Command::new(encoder)
+        .arg("-quiet")
+        .args(&["-q", &quality.to_string()])
+        .arg(&src)
+        .args(&["-o", &dst.to_string_lossy()]))?;
+
+    read_and_cleanup(&dst)
+}
+
+/// Encode an animation as animated WebP, honoring per-frame GIF delays.
+fn encode_webp_animation(encoder: &str,
+                         gif_anim: &GifAnimation,
+                         quality: u8) -> io::Result<Vec<u8>> {
+    let dir = scratch_dir()?;
+    let dst = dir.join("out.webp");
+
+    // `img2webp` accepts a sequence of frames with `-d <delay_ms>` preceding
+    // each one; build the argument list from the animation's timing.
+    let mut cmd = Command::new(encoder);
+    cmd.arg("-quiet").args(&["-q", &quality.to_string()]);
+    for (i, frame) in gif_anim.iter_frames().enumerate() {
+        let path = dir.join(format!("frame-{:04}.png", i));
+        frame.image.save(&path).map_err(to_io_error)?;
+        let delay_ms = frame.delay.subsec_nanos() / 1_000_000
+            + frame.delay.as_secs() as u32 * 1_000;
+        cmd.args(&["-d", &delay_ms.to_string()]).arg(path);
+    }
+    cmd.args(&["-o", &dst.to_string_lossy()]);
+
+    run_encoder(&mut cmd)?;
+    read_and_cleanup(&dst)
+}
+
+/// Encode a sequence of frames as an animated WebP with a uniform delay.
+fn encode_webp_frames<'a, I>(encoder: &str, frames: I,
+                             delay_ms: u32, quality: u8) -> io::Result<Vec<u8>>
+    where I: Iterator<Item=&'a DynamicImage>
+{
+    let dir = scratch_dir()?;
+    let dst = dir.join("out.webp");
+
+    let mut cmd = Command::new(encoder);
+    cmd.arg("-quiet").args(&["-q", &quality.to_string()]);
+    for (i, frame) in frames.enumerate() {
+        let path = dir.join(format!("frame-{:04}.png", i));
+        frame.save(&path).map_err(to_io_error)?;
+        cmd.args(&["-d", &delay_ms.to_string()]).arg(path);
+    }
+    cmd.args(&["-o", &dst.to_string_lossy()]);
+
+    run_encoder(&mut cmd)?;
+    read_and_cleanup(&dst)
+}
+
+/// A scratch directory for intermediate frames that removes itself (and
+/// everything under it) when dropped, whether its caller returns success or
+/// bails out early via `?` -- so a failed encode/decode can never leak it.
+struct ScratchDir(PathBuf);
+
+impl ::std::ops::Deref for ScratchDir {
+    type Target = Path;
+    fn deref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        ::std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Create a unique scratch directory for intermediate frames.
+fn scratch_dir() -> io::Result<ScratchDir> {
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+    static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir().join(format!("rofld-webp-{}", n));
+    fs::create_dir_all(&dir)?;
+    Ok(ScratchDir(dir))
+}
+
+/// Spawn the encoder and turn a non-zero exit into an `io::Error`.
+fn run_encoder(cmd: &mut Command) -> io::Result<()> {
+    let output = cmd.output()?;
+    if !output.status.success() {
+        io::stderr().write_all(&output.stderr).ok();
+        return Err(io::Error::new(io::ErrorKind::Other,
+            format!("WebP encoder exited with {}", output.status)));
+    }
+    Ok(())
+}
+
+/// Read back the encoder's output. The scratch directory itself is cleaned
+/// up by `ScratchDir`'s `Drop` impl once the caller's guard goes out of scope.
+fn read_and_cleanup(dst: &Path) -> io::Result<Vec<u8>> {
+    use std::fs;
+    fs::read(dst)
+}
+
+fn to_io_error(e: image::ImageError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Media (MIME) type string for an output image format.
+pub fn media_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::PNG => "image/png",
+        ImageFormat::JPEG => "image/jpeg",
+        ImageFormat::GIF => "image/gif",
+        ImageFormat::WEBP => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Canonical file extension for an output image format.
+pub fn extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::PNG => "png",
+        ImageFormat::JPEG => "jpg",
+        ImageFormat::GIF => "gif",
+        ImageFormat::WEBP => "webp",
+        _ => "bin",
+    }
+}
+
 impl fmt::Debug for Template {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -99,6 +383,10 @@ impl fmt::Debug for Template {
             Template::Animation(ref gif_anim) => {
                 write!(fmt, "Template::Animation({} frame(s))", gif_anim.frames_count())
             }
+            Template::Video(ref clip) => {
+                write!(fmt, "Template::Video({} frame(s), {:.3} fps, {:?})",
+                    clip.frames_count(), clip.fps, clip.container)
+            }
         }
     }
 }
@@ -112,44 +400,500 @@ impl<P: AsRef<Path>> TryFrom<P> for Template {
     fn try_from(path: P) -> Result<Self, Self::Err> {
         let path = path.as_ref();
 
+        // Videos are decoded out-of-process by ffmpeg; detect them first
+        // by extension (and, as a fallback, magic bytes).
+        let template = if let Some(container) = detect_video(&path) {
+            trace!("Image {} is a {:?} video", path.display(), container);
+            let clip = decode_video(&path, container).map_err(|e| {
+                error!("Failed to decode video template {}: {}", path.display(), e); e
+            })?;
+            Template::for_video(clip)
         // Use the `gif` crate to load animated GIFs.
         // Use the regular `image` crate to load any other (still) image.
-        if is_gif(&path) && is_gif_animated(&path).unwrap_or(false) {
+        } else if is_gif(&path) && is_gif_animated(&path).unwrap_or(false) {
             trace!("Image {} is an animated GIF", path.display());
+            // Read the frame count straight from the GIF header and reject
+            // oversized animations *before* decoding every frame, so that a
+            // malicious many-frame GIF can't exhaust memory first.
+            if let Some(count) = animated_gif::count_frames(&path) {
+                check_frame_count(count)?;
+            }
             let gif_anim = animated_gif::decode(&path).map_err(|e| {
                 error!("Failed to open animated GIF template {}: {}",
                     path.display(), e); e
             })?;
-            Ok(Template::for_gif_animation(gif_anim))
+            Template::for_gif_animation(gif_anim)
         } else {
             trace!("Opening image {}", path.display());
             let img = image::open(&path)?;
-            Ok(Template::for_image(img, &path))
+            Template::for_image(img, &path)
+        };
+
+        // Enforce the configured ingestion limits on the loaded template.
+        check_frame_count(template.image_count())?;
+        for img in template.iter_images() {
+            let (width, height) = img.dimensions();
+            check_dimensions(width, height)?;
         }
+        Ok(template)
     }
 }
 
+// Ingestion limits.
+//
+// Borrowing pict-rs' media-validation model, template loading enforces an
+// optional maximum width/height and frame count. A limit of zero means "no
+// limit"; the values are configured via `set_max_dimensions`/`set_max_frames`,
+// the same way the thread count and cache sizes flow in through `set_config`.
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+// Like `WEBP_ENABLED` above, these ingestion limits are the kind of setting
+// `EngineConfig` exists for; they're process-global statics only because
+// `caption::engine` isn't part of this checkout to hold them instead.
+static MAX_WIDTH: AtomicUsize = ATOMIC_USIZE_INIT;
+static MAX_HEIGHT: AtomicUsize = ATOMIC_USIZE_INIT;
+static MAX_FRAMES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set the maximum allowed template dimensions (0 disables the check).
+pub fn set_max_dimensions(width: u32, height: u32) {
+    MAX_WIDTH.store(width as usize, Ordering::Relaxed);
+    MAX_HEIGHT.store(height as usize, Ordering::Relaxed);
+}
+
+/// Set the maximum allowed number of frames in a template (0 disables it).
+pub fn set_max_frames(count: usize) {
+    MAX_FRAMES.store(count, Ordering::Relaxed);
+}
+
+fn check_dimensions(width: u32, height: u32) -> Result<(), TemplateError> {
+    let max_w = MAX_WIDTH.load(Ordering::Relaxed);
+    let max_h = MAX_HEIGHT.load(Ordering::Relaxed);
+    if (max_w > 0 && width as usize > max_w) || (max_h > 0 && height as usize > max_h) {
+        return Err(TemplateError::TooLarge{width, height});
+    }
+    Ok(())
+}
+
+fn check_frame_count(count: usize) -> Result<(), TemplateError> {
+    let max = MAX_FRAMES.load(Ordering::Relaxed);
+    if max > 0 && count > max {
+        return Err(TemplateError::TooManyFrames{count});
+    }
+    Ok(())
+}
+
 custom_derive! {
     #[derive(Debug,
              Error("template loading error"), ErrorDisplay, ErrorFrom)]
     pub enum TemplateError {
         OpenImage(image::ImageError),
         DecodeAnimatedGif(animated_gif::DecodeError),
+        DecodeVideo(io::Error),
+        TooLarge { width: u32, height: u32 },
+        TooManyFrames { count: usize },
+        InvalidName { name: String },
+        Download(StatusCode),
+        Fetch(hyper::Error),
+    }
+}
+
+
+// Remote templates.
+//
+// A template reference may be an `http(s)://` URL in addition to a local name
+// resolved against `TEMPLATE_DIR`. Fetching is gated behind a configurable
+// host allowlist so the server can't be abused as an open proxy / SSRF vector.
+
+lazy_static! {
+    /// Hosts permitted as sources for remote templates. Empty means no remote
+    /// loading is allowed at all.
+    static ref ALLOWED_HOSTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+/// Whether a template reference is a remote URL rather than a local name.
+pub fn is_remote(reference: &str) -> bool {
+    reference.starts_with("http://") || reference.starts_with("https://")
+}
+
+/// Configure the set of hosts from which remote templates may be fetched.
+pub fn set_allowed_hosts<I, S>(hosts: I)
+    where I: IntoIterator<Item=S>, S: Into<String>
+{
+    *ALLOWED_HOSTS.lock() = hosts.into_iter().map(Into::into).collect();
+}
+
+/// Extract the host component of an `http(s)://` URL.
+fn url_host(url: &str) -> Option<&str> {
+    let rest = url.splitn(2, "://").nth(1)?;
+    let authority = rest.splitn(2, '/').next()?;
+    // Strip any userinfo and port.
+    let authority = authority.rsplitn(2, '@').next()?;
+    Some(authority.splitn(2, ':').next()?)
+}
+
+/// Check a remote URL against the configured host allowlist.
+pub fn is_host_allowed(url: &str) -> bool {
+    match url_host(url) {
+        Some(host) => ALLOWED_HOSTS.lock().iter().any(|h| h == host),
+        None => false,
+    }
+}
+
+thread_local! {
+    // `Core`/`Handle` aren't `Send`, so they can't be shared across the
+    // captioner's cpupool threads the way a single reactor on the main event
+    // loop could be -- and blocking here on a request back to the main
+    // reactor would serialize every worker thread's remote fetches through
+    // it, one at a time. A thread-local `Core` is the next best thing: it's
+    // still spun up once per cpupool worker rather than once per call, so a
+    // busy worker thread that resolves many remote templates reuses the same
+    // reactor instead of paying the setup cost on every fetch. Lazily
+    // initialized (rather than eagerly in the `thread_local!` initializer)
+    // so that a failure to create it surfaces as an ordinary
+    // `TemplateError::Fetch` on that one request instead of panicking the
+    // cpupool worker thread.
+    static REMOTE_FETCH_CORE: RefCell<Option<::tokio_core::reactor::Core>> =
+        RefCell::new(None);
+}
+
+/// Fetch the bytes of a remote template over HTTP.
+///
+/// The GET runs on this cpupool worker thread's reactor (see
+/// `REMOTE_FETCH_CORE`; this is called from the captioner's cpupool, off the
+/// main event loop), turning a non-2xx response into
+/// `TemplateError::Download` and any transport failure into
+/// `TemplateError::Fetch`.
+fn fetch_remote(url: &str) -> Result<Vec<u8>, TemplateError> {
+    use futures::{Future, Stream};
+    use hyper::Client;
+
+    let uri = url.parse().map_err(|e| TemplateError::Fetch(hyper::Error::Uri(e)))?;
+
+    REMOTE_FETCH_CORE.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        if slot.is_none() {
+            let new_core = ::tokio_core::reactor::Core::new()
+                .map_err(|e| TemplateError::Fetch(hyper::Error::Io(e)))?;
+            *slot = Some(new_core);
+        }
+        let core = slot.as_mut().unwrap();
+
+        let client = Client::new(&core.handle());
+        let request = client.get(uri).and_then(|res| {
+            let status = res.status();
+            res.body().concat2().map(move |body| (status, body.to_vec()))
+        });
+        let (status, body) = core.run(request).map_err(TemplateError::Fetch)?;
+        if !status.is_success() {
+            return Err(TemplateError::Download(status));
+        }
+        Ok(body)
+    })
+}
+
+/// Decode template bytes fetched from a remote URL.
+///
+/// The URL's host must be on the allowlist (see `set_allowed_hosts`). The body
+/// (fetched by `fetch_remote`) is run through the same format-detection and
+/// ingestion-limit path as a local file by materializing it to a temp file.
+/// The engine caches the resulting `Template` keyed by URL in the template LRU
+/// cache, the same way it caches locally-resolved names.
+pub fn decode_remote(url: &str, body: &[u8]) -> Result<Template, TemplateError> {
+    use std::fs;
+
+    if !is_host_allowed(url) {
+        warn!("Refusing to load remote template from disallowed host: {}", url);
+        return Err(TemplateError::Download(StatusCode::Forbidden));
+    }
+
+    let ext = detect_extension(body).ok_or_else(|| TemplateError::OpenImage(
+        image::ImageError::UnsupportedError("unrecognized template format".into())))?;
+
+    let dir = scratch_dir().map_err(TemplateError::DecodeVideo)?;
+    let path = dir.join(format!("remote.{}", ext));
+    fs::write(&path, body).map_err(TemplateError::DecodeVideo)?;
+
+    Template::try_from(&path)
+}
+
+
+// Video templates, decoded via an external ffmpeg process.
+//
+// Following pict-rs' approach of driving ffmpeg as a plain subprocess (rather
+// than linking native bindings), we shell out to extract frames and probe the
+// source frame rate, then -- after captioning -- re-mux the captioned frames
+// back into the original container at the original fps.
+
+/// Name of the ffmpeg binary. Configurable at runtime via `set_ffmpeg`.
+pub const DEFAULT_FFMPEG: &'static str = "ffmpeg";
+
+/// Name of the ffprobe binary (usually shipped alongside ffmpeg).
+pub const DEFAULT_FFPROBE: &'static str = "ffprobe";
+
+lazy_static! {
+    /// External ffmpeg/ffprobe binaries used to decode and re-mux video
+    /// templates. Configurable at runtime via `set_ffmpeg` (see `set_config`).
+    ///
+    /// Same caveat as `WEBP_ENABLED`/`MAX_WIDTH` et al.: this and
+    /// `FFPROBE_BIN`/`FFMPEG_ENABLED` below belong on `EngineConfig`, but
+    /// that requires `caption::engine`, which this checkout doesn't have.
+    static ref FFMPEG_BIN: Mutex<String> = Mutex::new(DEFAULT_FFMPEG.to_owned());
+    static ref FFPROBE_BIN: Mutex<String> = Mutex::new(DEFAULT_FFPROBE.to_owned());
+    /// Whether video-template support is enabled. On by default; turning it off
+    /// lets the server degrade gracefully on hosts without ffmpeg installed.
+    static ref FFMPEG_ENABLED: ::std::sync::atomic::AtomicBool =
+        ::std::sync::atomic::AtomicBool::new(true);
+}
+
+/// Configure the ffmpeg/ffprobe binaries and whether video support is enabled.
+pub fn set_ffmpeg<S: Into<String>>(enabled: bool, ffmpeg: S, ffprobe: S) {
+    *FFMPEG_BIN.lock() = ffmpeg.into();
+    *FFPROBE_BIN.lock() = ffprobe.into();
+    FFMPEG_ENABLED.store(enabled, ::std::sync::atomic::Ordering::Relaxed);
+    debug!("Video support {}", if enabled { "enabled" } else { "disabled" });
+}
+
+fn ffmpeg_bin() -> String { FFMPEG_BIN.lock().clone() }
+fn ffprobe_bin() -> String { FFPROBE_BIN.lock().clone() }
+fn ffmpeg_enabled() -> bool {
+    FFMPEG_ENABLED.load(::std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Detect whether a path refers to a supported video container.
+fn detect_video<P: AsRef<Path>>(path: P) -> Option<VideoContainer> {
+    let path = path.as_ref();
+    // With video support disabled we simply don't recognize the container, so
+    // the loader falls through to the still-image path and fails cleanly there
+    // rather than shelling out to a missing ffmpeg.
+    if !ffmpeg_enabled() {
+        return None;
+    }
+    if let Some(container) = path.extension()
+        .and_then(|s| s.to_str())
+        .and_then(VideoContainer::from_extension)
+    {
+        return Some(container);
+    }
+    // Fall back to magic bytes: MP4 files carry an `ftyp` box near the start,
+    // WebM files start with the EBML header `1A 45 DF A3`.
+    use std::fs::File;
+    use std::io::Read;
+    let mut header = [0u8; 12];
+    if File::open(path).and_then(|mut f| f.read_exact(&mut header)).is_ok() {
+        if &header[4..8] == b"ftyp" {
+            return Some(VideoContainer::Mp4);
+        }
+        if header[..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+            return Some(VideoContainer::WebM);
+        }
+    }
+    None
+}
+
+/// Decode a video file into frames by invoking ffmpeg.
+fn decode_video<P: AsRef<Path>>(path: P, container: VideoContainer)
+    -> Result<VideoClip, TemplateError>
+{
+    let path = path.as_ref();
+    let dir = scratch_dir().map_err(TemplateError::DecodeVideo)?;
+
+    // Extract every frame as a PNG: `ffmpeg -i in.mp4 -f image2 frame-%04d.png`.
+    let pattern = dir.join("frame-%04d.png");
+    run_ffmpeg(Command::new(ffmpeg_bin())
+        .arg("-loglevel").arg("error")
+        .arg("-i").arg(path)
+        .arg("-f").arg("image2")
+        .arg(&pattern))?;
+
+    // Collect the extracted frames in order.
+    let mut paths: Vec<PathBuf> = glob::glob(&dir.join("frame-*.png").to_string_lossy())
+        .map_err(|e| TemplateError::DecodeVideo(
+            io::Error::new(io::ErrorKind::Other, e)))?
+        .filter_map(Result::ok)
+        .collect();
+    paths.sort();
+    let mut frames = Vec::with_capacity(paths.len());
+    for p in &paths {
+        frames.push(image::open(p)?);
+    }
+
+    let fps = probe_fps(path).unwrap_or(DEFAULT_VIDEO_FPS);
+    Ok(VideoClip{frames, fps, container})
+}
+
+/// Frame rate assumed when ffprobe can't determine the source fps.
+const DEFAULT_VIDEO_FPS: f64 = 25.0;
+
+/// Probe the average frame rate of a video using ffprobe.
+fn probe_fps(path: &Path) -> Option<f64> {
+    let output = Command::new(ffprobe_bin())
+        .args(&["-v", "error", "-select_streams", "v:0",
+                "-show_entries", "stream=r_frame_rate",
+                "-of", "default=nokey=1:noprint_wrappers=1"])
+        .arg(path)
+        .output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // r_frame_rate comes back as a rational like "30000/1001".
+    let text = String::from_utf8_lossy(&output.stdout);
+    let text = text.trim();
+    let mut parts = text.splitn(2, '/');
+    let num: f64 = parts.next()?.parse().ok()?;
+    let den: f64 = parts.next().and_then(|d| d.parse().ok()).unwrap_or(1.0);
+    if den == 0.0 { None } else { Some(num / den) }
+}
+
+impl Template {
+    /// Re-encode this template into its native container's bytes for the output
+    /// layer to serve after captioning. Video clips are re-muxed by ffmpeg at
+    /// their original frame rate; other variants have no video container and
+    /// yield `None` (the caller uses the still/animated path instead).
+    pub fn encode_video_output(&self) -> Option<Result<Vec<u8>, TemplateError>> {
+        match *self {
+            Template::Video(ref clip) => {
+                let frames: Vec<DynamicImage> = clip.iter_frames().cloned().collect();
+                Some(encode_video((&frames, clip.fps, clip.container)))
+            }
+            _ => None,
+        }
     }
 }
 
+/// Re-mux captioned frames back into the clip's original container at its fps.
+pub fn encode_video(clip_meta: (&[DynamicImage], f64, VideoContainer))
+    -> Result<Vec<u8>, TemplateError>
+{
+    let (frames, fps, container) = clip_meta;
+    let dir = scratch_dir().map_err(TemplateError::DecodeVideo)?;
+    let dst = dir.join(format!("out.{}", container.extension()));
+
+    for (i, frame) in frames.iter().enumerate() {
+        frame.save(dir.join(format!("frame-{:04}.png", i)))?;
+    }
+
+    run_ffmpeg(Command::new(ffmpeg_bin())
+        .arg("-loglevel").arg("error")
+        .arg("-framerate").arg(format!("{}", fps))
+        .arg("-i").arg(dir.join("frame-%04d.png"))
+        .arg("-pix_fmt").arg("yuv420p")
+        .arg(&dst))?;
+
+    ::std::fs::read(&dst).map_err(TemplateError::DecodeVideo)
+}
+
+/// Spawn ffmpeg and map spawn/exit failures onto `TemplateError::DecodeVideo`.
+fn run_ffmpeg(cmd: &mut Command) -> Result<(), TemplateError> {
+    let output = cmd.output().map_err(TemplateError::DecodeVideo)?;
+    if !output.status.success() {
+        let msg = format!("ffmpeg exited with {}: {}",
+            output.status, String::from_utf8_lossy(&output.stderr));
+        return Err(TemplateError::DecodeVideo(
+            io::Error::new(io::ErrorKind::Other, msg)));
+    }
+    Ok(())
+}
+
 
 lazy_static! {
-    static ref TEMPLATE_DIR: PathBuf =
-        env::current_dir().unwrap().join("data").join("templates");
+    /// Directory the templates are loaded from. Defaults to
+    /// `$CWD/data/templates` but can be overridden at runtime via the
+    /// `--template-dir` flag (see `set_template_dir`).
+    static ref TEMPLATE_DIR: Mutex<PathBuf> = Mutex::new(
+        env::current_dir().unwrap().join("data").join("templates"));
+}
+
+/// Override the directory that templates are loaded from.
+pub fn set_template_dir<P: Into<PathBuf>>(path: P) {
+    let path = path.into();
+    debug!("Template directory set to {}", path.display());
+    *TEMPLATE_DIR.lock() = path;
+}
+
+/// The directory templates are currently loaded from.
+pub fn template_dir() -> PathBuf {
+    TEMPLATE_DIR.lock().clone()
+}
+
+/// Watch the template directory and invoke `on_change` with a template name
+/// whenever its backing file is created, modified, or removed.
+///
+/// This spawns a background polling thread (comparing file modification times
+/// every `interval`), so that editing or replacing a template on disk takes
+/// effect without bouncing the process. The captioner uses the callback to
+/// drop the affected entry from the template cache.
+pub fn watch_changes<F>(interval: Duration, on_change: F)
+    where F: Fn(&str) + Send + 'static
+{
+    use std::collections::HashMap;
+    use std::fs;
+    use std::thread;
+    use std::time::SystemTime;
+
+    thread::spawn(move || {
+        let mut mtimes: HashMap<String, SystemTime> = HashMap::new();
+        let mut first = true;
+        loop {
+            let mut seen = HashMap::new();
+            if let Ok(entries) = fs::read_dir(template_dir()) {
+                for entry in entries.filter_map(Result::ok) {
+                    let path = entry.path();
+                    let name = match path.file_stem().and_then(|s| s.to_str()) {
+                        Some(n) => n.to_owned(),
+                        None => continue,
+                    };
+                    let mtime = entry.metadata().and_then(|m| m.modified()).ok();
+                    if let Some(mtime) = mtime {
+                        if !first && mtimes.get(&name) != Some(&mtime) {
+                            trace!("Detected change to template `{}`", name);
+                            on_change(&name);
+                        }
+                        seen.insert(name, mtime);
+                    }
+                }
+            }
+            // Report templates that disappeared from the directory, too.
+            if !first {
+                for name in mtimes.keys() {
+                    if !seen.contains_key(name) {
+                        trace!("Template `{}` was removed", name);
+                        on_change(name);
+                    }
+                }
+            }
+            mtimes = seen;
+            first = false;
+            thread::sleep(interval);
+        }
+    });
 }
 
 /// Load an image macro template.
 pub fn load(template: &str) -> Option<Template> {
     debug!("Loading image macro template `{}`", template);
 
+    // A remote reference is fetched (on the cpupool thread this runs on) and
+    // decoded via `decode_remote`; the engine caches the resulting `Template`
+    // keyed by the reference (i.e. the URL), just like a local name.
+    if is_remote(template) {
+        if !is_host_allowed(template) {
+            warn!("Refusing to load remote template from disallowed host: {}", template);
+            return None;
+        }
+        return match fetch_remote(template).and_then(|body| decode_remote(template, &body)) {
+            Ok(t) => {
+                debug!("Remote template `{}` fetched successfully", template);
+                Some(t)
+            }
+            Err(e) => {
+                error!("Failed to load remote template `{}`: {}", template, e);
+                None
+            }
+        };
+    }
+
     let template_glob = &format!(
-        "{}", TEMPLATE_DIR.join(template.to_owned() + ".*").display());
+        "{}", template_dir().join(template.to_owned() + ".*").display());
     let mut template_iter = match glob::glob(template_glob) {
         Ok(it) => it,
         Err(e) => {
@@ -174,13 +918,90 @@ pub fn load(template: &str) -> Option<Template> {
 }
 
 
+// Storing templates
+
+/// Whether `name` is safe to use as a template's on-disk file stem.
+///
+/// Must be a single path component: no separators, no NUL, and not `.`/`..`,
+/// so that joining it onto `template_dir()` can never escape that directory.
+fn is_valid_name(name: &str) -> bool {
+    name != "" && name != "." && name != ".."
+        && !name.contains('/') && !name.contains('\\') && !name.contains('\0')
+}
+
+/// Detect the file extension to store uploaded bytes under, or `None` if the
+/// payload isn't a supported image/animation/video format.
+pub fn detect_extension(bytes: &[u8]) -> Option<&'static str> {
+    // Videos are sniffed from their container magic bytes.
+    if bytes.len() >= 12 {
+        if &bytes[4..8] == b"ftyp" {
+            return Some("mp4");
+        }
+        if bytes[..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+            return Some("webm");
+        }
+    }
+    image::guess_format(bytes).ok().map(extension)
+}
+
+/// Store an uploaded template under the template directory, making it
+/// immediately available to `load()` and `list()`.
+///
+/// The bytes are written to disk with an extension inferred from their format
+/// and then validated through the regular `Template::try_from` path (which
+/// also enforces the configured ingestion limits); an invalid upload leaves no
+/// file behind. Any pre-existing template of the same name is replaced.
+pub fn store(name: &str, bytes: &[u8]) -> Result<PathBuf, TemplateError> {
+    use std::fs;
+
+    if !is_valid_name(name) {
+        warn!("Refusing to store template with invalid name `{}`", name);
+        return Err(TemplateError::InvalidName{name: name.to_owned()});
+    }
+
+    let ext = detect_extension(bytes).ok_or_else(|| TemplateError::OpenImage(
+        image::ImageError::UnsupportedError("unrecognized template format".into())))?;
+
+    let dir = template_dir();
+    fs::create_dir_all(&dir).map_err(TemplateError::DecodeVideo)?;
+    let path = dir.join(format!("{}.{}", name, ext));
+
+    // Remove any pre-existing file for this name under a *different*
+    // extension -- otherwise re-uploading under a new format (e.g. replacing
+    // a `.png` with a `.webp`) would leave both on disk, with `list()`
+    // reporting the name twice and `load()` free to resolve either one.
+    let stale_pattern = dir.join(format!("{}.*", name));
+    if let Ok(matches) = glob::glob(&stale_pattern.to_string_lossy()) {
+        for stale in matches.filter_map(Result::ok) {
+            if stale != path {
+                fs::remove_file(&stale).ok();
+            }
+        }
+    }
+
+    fs::write(&path, bytes).map_err(TemplateError::DecodeVideo)?;
+
+    // Validate the freshly written file; drop it if it doesn't load.
+    match Template::try_from(&path) {
+        Ok(_) => {
+            debug!("Stored template `{}` at {}", name, path.display());
+            Ok(path)
+        }
+        Err(e) => {
+            fs::remove_file(&path).ok();
+            Err(e)
+        }
+    }
+}
+
+
 // Other
 
 /// List all available template names.
 pub fn list() -> Vec<String> {
     debug!("Listing all available templates...");
 
-    let pattern = format!("{}", TEMPLATE_DIR.join("*.*").display());
+    let pattern = format!("{}", template_dir().join("*.*").display());
     trace!("Globbing with {}", pattern);
     let templates = glob::glob(&pattern).unwrap()
         .filter_map(Result::ok)  // TODO: report errors about this
@@ -192,3 +1013,178 @@ pub fn list() -> Vec<String> {
     debug!("{} template(s) found", templates.len());
     templates
 }
+
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, ImageFormat};
+    use spectral::prelude::*;
+    use super::*;
+
+    #[test]
+    fn preferred_format_webp_respects_the_feature_flag() {
+        let template = Template::Image(DynamicImage::new_rgba8(1, 1), ImageFormat::WEBP);
+
+        set_webp_enabled(false);
+        assert_that!(template.preferred_format()).is_equal_to(DEFAULT_IMAGE_FORMAT);
+
+        set_webp_enabled(true);
+        assert_that!(template.preferred_format()).is_equal_to(ImageFormat::WEBP);
+
+        set_webp_enabled(false);
+    }
+
+    #[test]
+    fn still_image_formats_are_always_preferred_verbatim() {
+        for &format in &[ImageFormat::PNG, ImageFormat::JPEG] {
+            let template = Template::Image(DynamicImage::new_rgba8(1, 1), format);
+            assert_that!(template.preferred_format()).is_equal_to(format);
+        }
+    }
+
+    #[test]
+    fn url_host_extracts_the_authority() {
+        assert_that!(url_host("http://example.com/cat.png")).is_equal_to(Some("example.com"));
+        assert_that!(url_host("https://example.com:8443/x")).is_equal_to(Some("example.com"));
+        assert_that!(url_host("http://user:pw@host.test/a/b")).is_equal_to(Some("host.test"));
+        assert_that!(url_host("not-a-url")).is_equal_to(None);
+    }
+
+    #[test]
+    fn host_allowlist_gates_remote_references() {
+        set_allowed_hosts(vec!["example.com"]);
+        assert_that!(is_host_allowed("http://example.com/cat.png")).is_true();
+        assert_that!(is_host_allowed("http://evil.test/cat.png")).is_false();
+        set_allowed_hosts(Vec::<String>::new());
+        assert_that!(is_host_allowed("http://example.com/cat.png")).is_false();
+    }
+
+    #[test]
+    fn video_container_round_trips_extensions() {
+        assert_that!(VideoContainer::from_extension("mp4"))
+            .is_equal_to(Some(VideoContainer::Mp4));
+        assert_that!(VideoContainer::from_extension("WEBM"))
+            .is_equal_to(Some(VideoContainer::WebM));
+        assert_that!(VideoContainer::from_extension("gif")).is_equal_to(None);
+        assert_that!(VideoContainer::Mp4.extension()).is_equal_to("mp4");
+        assert_that!(VideoContainer::WebM.extension()).is_equal_to("webm");
+    }
+
+    #[test]
+    fn encode_video_output_only_applies_to_video_templates() {
+        let image = Template::Image(DynamicImage::new_rgba8(1, 1), ImageFormat::PNG);
+        assert_that!(image.encode_video_output().is_none()).is_true();
+    }
+
+    // Dimension and frame-count limits share process-global state, so they're
+    // asserted together in one test to avoid racing the parallel test runner.
+    #[test]
+    fn ingestion_limits_reject_oversized_templates() {
+        set_max_dimensions(10, 10);
+        assert_that!(check_dimensions(8, 8).is_ok()).is_true();
+        match check_dimensions(20, 5) {
+            Err(TemplateError::TooLarge{width, height}) => {
+                assert_that!(width).is_equal_to(20);
+                assert_that!(height).is_equal_to(5);
+            }
+            other => panic!("expected TooLarge, got {:?}", other),
+        }
+        set_max_dimensions(0, 0);
+        assert_that!(check_dimensions(9999, 9999).is_ok()).is_true();
+
+        set_max_frames(3);
+        assert_that!(check_frame_count(3).is_ok()).is_true();
+        match check_frame_count(4) {
+            Err(TemplateError::TooManyFrames{count}) => assert_that!(count).is_equal_to(4),
+            other => panic!("expected TooManyFrames, got {:?}", other),
+        }
+        set_max_frames(0);
+        assert_that!(check_frame_count(10_000).is_ok()).is_true();
+    }
+
+    #[test]
+    fn detect_extension_sniffs_supported_formats() {
+        // PNG signature.
+        let png = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0, 0, 0, 0];
+        assert_that!(detect_extension(&png)).is_equal_to(Some("png"));
+        // MP4 `ftyp` box.
+        let mut mp4 = vec![0, 0, 0, 0];
+        mp4.extend_from_slice(b"ftypmp42");
+        assert_that!(detect_extension(&mp4)).is_equal_to(Some("mp4"));
+        // WebM EBML header.
+        let webm = [0x1A, 0x45, 0xDF, 0xA3, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_that!(detect_extension(&webm)).is_equal_to(Some("webm"));
+        // Anything unrecognized yields None -- the upload handler's 415 case.
+        assert_that!(detect_extension(&[0u8; 16])).is_equal_to(None);
+    }
+
+    #[test]
+    fn media_type_and_extension_cover_output_formats() {
+        assert_that!(media_type(ImageFormat::PNG)).is_equal_to("image/png");
+        assert_that!(media_type(ImageFormat::WEBP)).is_equal_to("image/webp");
+        assert_that!(extension(ImageFormat::JPEG)).is_equal_to("jpg");
+        assert_that!(extension(ImageFormat::GIF)).is_equal_to("gif");
+    }
+
+    #[test]
+    fn is_valid_name_rejects_path_traversal() {
+        assert_that!(is_valid_name("my-template")).is_true();
+        assert_that!(is_valid_name("")).is_false();
+        assert_that!(is_valid_name(".")).is_false();
+        assert_that!(is_valid_name("..")).is_false();
+        assert_that!(is_valid_name("../../etc/passwd")).is_false();
+        assert_that!(is_valid_name("a/b")).is_false();
+        assert_that!(is_valid_name("a\\b")).is_false();
+        assert_that!(is_valid_name("/etc/passwd")).is_false();
+    }
+
+    #[test]
+    fn store_rejects_invalid_names_before_touching_disk() {
+        match store("../escape", b"irrelevant") {
+            Err(TemplateError::InvalidName{name}) => assert_that!(name).is_equal_to("../escape".to_owned()),
+            other => panic!("expected InvalidName, got {:?}", other.map(|p| p.display().to_string())),
+        }
+    }
+
+    // `template_dir` is process-global state, same caveat as the ingestion
+    // limits above -- give this test its own directory so it doesn't race
+    // other tests that touch it.
+    #[test]
+    fn store_replaces_a_same_name_template_under_a_different_extension() {
+        use std::fs;
+
+        let dir = env::temp_dir().join("rofld-test-store-replace");
+        fs::create_dir_all(&dir).ok();
+        set_template_dir(dir.clone());
+
+        // PNG and JPEG stand in for the PNG/WebP re-upload in the bug report:
+        // both are encodable without shelling out to an external tool, and
+        // the bug is about `store()`'s own stale-file cleanup, not about
+        // which two formats are involved.
+        let png = DynamicImage::new_rgba8(1, 1);
+        let png_path = dir.join("probe.png");
+        png.save(&png_path).unwrap();
+        let png_bytes = fs::read(&png_path).unwrap();
+        fs::remove_file(&png_path).ok();
+
+        let jpg = DynamicImage::ImageRgb8(image::RgbImage::new(2, 2));
+        let jpg_path = dir.join("probe.jpg");
+        jpg.save(&jpg_path).unwrap();
+        let jpg_bytes = fs::read(&jpg_path).unwrap();
+        fs::remove_file(&jpg_path).ok();
+
+        let first = store("dup", &png_bytes).expect("storing the PNG should succeed");
+        assert_that!(first.extension().and_then(|e| e.to_str())).is_equal_to(Some("png"));
+        assert_that!(list().iter().filter(|n| *n == "dup").count()).is_equal_to(1);
+
+        let second = store("dup", &jpg_bytes).expect("storing the JPEG should succeed");
+        assert_that!(second.extension().and_then(|e| e.to_str())).is_equal_to(Some("jpg"));
+        assert_that!(first.exists()).is_false();
+        assert_that!(list().iter().filter(|n| *n == "dup").count()).is_equal_to(1);
+
+        let loaded = load("dup").expect("the replaced template should still load");
+        assert_that!(loaded.iter_images().next().unwrap().dimensions()).is_equal_to((2, 2));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,8 +1,10 @@
 //! Module implementing the thread pool that does the image captioning.
 //! This is used by the /caption request handler.
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::time::Duration;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 use std::sync::Arc;
 
 use antidote::Mutex;
@@ -26,11 +28,24 @@ lazy_static! {
     pub static ref CAPTIONER: Arc<Captioner> = Arc::new(Captioner::new());
 }
 
+/// Default capacity of the rendered-output cache.
+const DEFAULT_RENDER_CACHE_CAPACITY: usize = 128;
+
 /// Renders image macros into captioned images.
 pub struct Captioner {
     pool: Mutex<CpuPool>,
     engine: rofl::Engine,
     timer: Timer,
+    /// Cache of already-rendered outputs, keyed by a stable hash of the
+    /// normalized `ImageMacro` (plus the encoding quality settings and the
+    /// current resource generation -- see `render_cache_key`).
+    render_cache: ThreadSafeCache<u64, CaptionOutput>,
+    /// Bumped every time a template or font is invalidated, so that stale
+    /// cached renders (from before a re-upload or live-reload) don't keep
+    /// being served out of `render_cache`. Folding it into the cache key is
+    /// simpler than trying to pick out just the affected entries, at the cost
+    /// of dropping the whole render cache on any single resource change.
+    render_generation: AtomicUsize,
     // Configuration params.
     task_timeout: Atomic<Duration>,
 }
@@ -42,10 +57,12 @@ impl Captioner {
         let engine = Self::engine_builder().build()
             .expect("failed to create rofl::Engine in Captioner::new");
         let timer = Timer::default();
+        let render_cache = ThreadSafeCache::new(DEFAULT_RENDER_CACHE_CAPACITY);
+        let render_generation = ATOMIC_USIZE_INIT;
 
         let task_timeout = Atomic::new(Duration::from_secs(0));
 
-        Captioner{pool, engine, timer, task_timeout}
+        Captioner{pool, engine, timer, render_cache, render_generation, task_timeout}
     }
 
     #[doc(hidden)]
@@ -81,6 +98,50 @@ impl Captioner {
     pub fn font_cache(&self) -> &ThreadSafeCache<String, Font> {
         self.engine.font_cache().unwrap()
     }
+
+    /// Accessor bundling operations on the template/font resource caches.
+    #[inline]
+    pub fn cache(&self) -> ResourceCaches {
+        ResourceCaches{captioner: self}
+    }
+}
+
+/// Operations on the template/font resource caches, as opposed to the
+/// rendered-output cache (see `Captioner::render_cache_key`).
+pub struct ResourceCaches<'a> {
+    captioner: &'a Captioner,
+}
+
+impl<'a> ResourceCaches<'a> {
+    #[inline]
+    pub fn set_template_capacity(&self, capacity: usize) -> &Self {
+        self.captioner.template_cache().set_capacity(capacity);
+        self
+    }
+
+    #[inline]
+    pub fn set_font_capacity(&self, capacity: usize) -> &Self {
+        self.captioner.font_cache().set_capacity(capacity);
+        self
+    }
+
+    /// Drop a stale template from the cache and bump the render generation,
+    /// so any render cached from before this change stops being served.
+    #[inline]
+    pub fn invalidate_template(&self, name: &str) -> &Self {
+        self.captioner.template_cache().invalidate(name);
+        self.captioner.bump_render_generation();
+        self
+    }
+
+    /// Drop a stale font from the cache and bump the render generation,
+    /// so any render cached from before this change stops being served.
+    #[inline]
+    pub fn invalidate_font(&self, name: &str) -> &Self {
+        self.captioner.font_cache().invalidate(name);
+        self.captioner.bump_render_generation();
+        self
+    }
 }
 
 // Configuration tweaks.
@@ -99,6 +160,13 @@ impl Captioner {
         self
     }
 
+    #[inline]
+    pub fn set_render_cache_capacity(&self, capacity: usize) -> &Self {
+        trace!("Setting rendered-output cache capacity to {}", capacity);
+        self.render_cache.set_capacity(capacity);
+        self
+    }
+
     #[inline]
     pub fn set_task_timeout(&self, timeout: Duration) -> &Self {
         let secs = timeout.as_secs();
@@ -163,9 +231,43 @@ impl Captioner {
 
 // Rendering code.
 impl Captioner {
+    /// Bump the render generation, invalidating every entry currently in
+    /// `render_cache` (see `render_cache_key`). Called whenever a template or
+    /// font is invalidated (re-upload, live-reload), so a stale rendering
+    /// from before the change can't be served out of the cache.
+    #[inline]
+    fn bump_render_generation(&self) {
+        self.render_generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Compute a stable cache key for an image macro.
+    ///
+    /// The key covers the full normalized `ImageMacro` (template name, all
+    /// text lines, alignment, colors, and size overrides) together with the
+    /// current JPEG/GIF quality settings and the render generation, so that a
+    /// change to either quality knob, or a template/font invalidation,
+    /// correctly invalidates previously cached outputs.
+    fn render_cache_key(&self, im: &ImageMacro) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        im.hash(&mut hasher);
+        let config = self.engine.config();
+        config.jpeg_quality.hash(&mut hasher);
+        config.gif_quality.hash(&mut hasher);
+        self.render_generation.load(Ordering::Relaxed).hash(&mut hasher);
+        hasher.finish()
+    }
+
     /// Render an image macro as PNG.
     /// The rendering is done in a separate thread.
     pub fn render(&self, im: ImageMacro) -> BoxFuture<CaptionOutput, RenderError> {
+        // Serve an identical, recently-rendered image macro straight from the
+        // cache without touching the thread pool.
+        let cache_key = self.render_cache_key(&im);
+        if let Some(out) = self.render_cache.get(&cache_key) {
+            debug!("Serving cached caption output for {:?}", im);
+            return future::ok((*out).clone()).boxed();
+        }
+
         let pool = match self.pool.try_lock() {
             Ok(p) => p,
             Err(_) => {
@@ -182,11 +284,14 @@ impl Captioner {
         let task_future = pool.spawn_fn({
             let im_repr = format!("{:?}", im);
             let engine = self.engine.clone();
+            let render_cache = self.render_cache.clone();
             move || {
                 match engine.caption(im) {
                     Ok(out) => {
                         debug!("Successfully rendered {} as {:?}, final result size: {} bytes",
                             im_repr, out.format(), out.len());
+                        // Remember the result so an identical request is a cache hit.
+                        render_cache.put(cache_key, out.clone());
                         future::ok(out)
                     },
                     Err(e) => {
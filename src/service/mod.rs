@@ -0,0 +1,5 @@
+//! Module implementing the HTTP service.
+
+mod upload;
+
+pub use self::upload::upload_template;
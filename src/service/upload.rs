@@ -0,0 +1,149 @@
+//! Handler for `POST /templates/{name}`: runtime template uploads.
+//!
+//! Mounted by the service router, this accepts a `multipart/form-data` body,
+//! validates the uploaded bytes through the regular `Template::try_from` path
+//! (which also enforces the configured ingestion limits), stores the file
+//! under the template directory so it becomes immediately available to the
+//! captioner and to `list()`, and drops any stale entry from the template
+//! cache so a re-upload takes effect without restarting the process.
+
+use futures::{future, Future, Stream};
+use hyper::{self, StatusCode};
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::{Request, Response};
+use mime;
+
+use caption::CAPTIONER;
+use resources::templates::{self, TemplateError};
+
+
+/// Maximum accepted size of an upload request body, in bytes.
+///
+/// This bounds memory use while the body is being buffered, well before
+/// `templates::store` gets a chance to decode it and enforce the real
+/// ingestion limits (dimensions, frame count) -- without it, a client could
+/// exhaust memory or disk with an oversized request regardless of what those
+/// limits are configured to.
+const MAX_UPLOAD_SIZE: u64 = 64 * 1024 * 1024;  // 64 MiB
+
+/// Handle an upload of the template named `name`.
+pub fn upload_template(name: String, req: Request)
+    -> Box<Future<Item=Response, Error=hyper::Error>>
+{
+    // The body has to be `multipart/form-data`; anything else is a 415.
+    let boundary = match req.headers().get::<ContentType>().and_then(multipart_boundary) {
+        Some(b) => b,
+        None => {
+            warn!("Rejecting non-multipart upload for template `{}`", name);
+            return Box::new(future::ok(
+                Response::new().with_status(StatusCode::UnsupportedMediaType)));
+        }
+    };
+
+    // Reject a declared-oversized body outright, without reading any of it.
+    if let Some(len) = req.headers().get::<ContentLength>() {
+        if len.0 > MAX_UPLOAD_SIZE {
+            warn!("Rejecting oversized upload for template `{}`: {} bytes declared",
+                name, len.0);
+            return Box::new(future::ok(
+                Response::new().with_status(StatusCode::PayloadTooLarge)));
+        }
+    }
+
+    // Buffer the body ourselves (rather than concat2()) so a body that lies
+    // about -- or omits -- its Content-Length still can't grow unbounded.
+    let body_name = name.clone();
+    let response = req.body().fold(Vec::new(), move |mut body, chunk| {
+        if body.len() + chunk.len() > MAX_UPLOAD_SIZE as usize {
+            warn!("Rejecting oversized upload for template `{}`: body exceeds {} bytes",
+                body_name, MAX_UPLOAD_SIZE);
+            return future::err(hyper::Error::TooLarge);
+        }
+        body.extend_from_slice(&chunk);
+        future::ok(body)
+    }).map(move |body| {
+        let file = match extract_file_part(&body, &boundary) {
+            Some(bytes) => bytes,
+            None => {
+                warn!("Malformed multipart upload for template `{}`", name);
+                return Response::new().with_status(StatusCode::BadRequest);
+            }
+        };
+        match templates::store(&name, file) {
+            Ok(path) => {
+                info!("Stored uploaded template `{}` at {}", name, path.display());
+                // Invalidate any stale cached template so the upload is served.
+                CAPTIONER.cache().invalidate_template(&name);
+                Response::new().with_status(StatusCode::Created)
+            }
+            Err(TemplateError::TooLarge{..}) | Err(TemplateError::TooManyFrames{..}) => {
+                warn!("Rejecting oversized upload for template `{}`", name);
+                Response::new().with_status(StatusCode::PayloadTooLarge)
+            }
+            Err(TemplateError::InvalidName{..}) => {
+                warn!("Rejecting upload with invalid template name `{}`", name);
+                Response::new().with_status(StatusCode::BadRequest)
+            }
+            Err(e) => {
+                warn!("Rejecting unsupported upload for template `{}`: {}", name, e);
+                Response::new().with_status(StatusCode::UnsupportedMediaType)
+            }
+        }
+    });
+    Box::new(response)
+}
+
+/// Extract the multipart boundary from a `multipart/form-data` content type.
+fn multipart_boundary(content_type: &ContentType) -> Option<String> {
+    let mime = &content_type.0;
+    if mime.type_() != mime::MULTIPART || mime.subtype() != mime::FORM_DATA {
+        return None;
+    }
+    mime.get_param(mime::BOUNDARY).map(|b| b.as_str().to_owned())
+}
+
+/// Pull the payload of the first file part out of a multipart body.
+///
+/// This reads just enough of the format to split fields from the file: parts
+/// are delimited by `--<boundary>`, their headers are separated from the data
+/// by a blank line, and the trailing CRLF before the next delimiter is dropped.
+fn extract_file_part<'b>(body: &'b [u8], boundary: &str) -> Option<&'b [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    let mut search = body;
+    while let Some(start) = find(search, delimiter) {
+        let part = &search[start + delimiter.len()..];
+        // The closing delimiter is `--<boundary>--`; stop there.
+        if part.starts_with(b"--") {
+            break;
+        }
+        if let Some(sep) = find(part, b"\r\n\r\n") {
+            let headers = &part[..sep];
+            let data = &part[sep + 4..];
+            if let Some(end) = find(data, delimiter) {
+                // Everything up to the CRLF preceding the next delimiter.
+                let data = &data[..end];
+                let data = data.split_at(data.len().saturating_sub(2)).0;
+                if contains(headers, b"filename=") {
+                    return Some(data);
+                }
+            }
+        }
+        search = part;
+    }
+    None
+}
+
+/// Find the first index of `needle` within `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Whether `haystack` contains `needle`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    find(haystack, needle).is_some()
+}
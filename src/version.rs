@@ -0,0 +1,65 @@
+//! Build-time version information.
+//!
+//! The constants are generated by the build script (see `build.rs`) and
+//! included below; `BuildInfo` wraps them into a typed, displayable record
+//! that the server can advertise in logs and a `/version` response.
+
+use std::fmt;
+
+// Pulls in: COMMIT_HASH, COMMIT_DATE, CHANNEL, WORKTREE_CLEAN.
+include!(concat!(env!("OUT_DIR"), "/build-info.rs"));
+
+
+/// Structured information about the current build.
+#[derive(Clone, Copy, Debug)]
+pub struct BuildInfo {
+    /// Crate version, as filled out by Cargo.
+    pub version: &'static str,
+    /// Short Git SHA of the build, if known.
+    pub commit_hash: Option<&'static str>,
+    /// Commit date (YYYY-MM-DD), if known.
+    pub commit_date: Option<&'static str>,
+    /// Release channel (e.g. `dev`, `stable`).
+    pub channel: &'static str,
+    /// Whether the working tree was clean at build time, if known.
+    pub worktree_clean: Option<bool>,
+}
+
+/// Return the build information for this binary.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit_hash: COMMIT_HASH,
+        commit_date: COMMIT_DATE,
+        channel: CHANNEL,
+        worktree_clean: WORKTREE_CLEAN,
+    }
+}
+
+impl BuildInfo {
+    /// The commit hash, or the `"unknown"` sentinel for builds made without
+    /// Git information (e.g. from a crates.io source tarball).
+    #[inline]
+    pub fn commit(&self) -> &'static str {
+        self.commit_hash.unwrap_or("unknown")
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    /// Formats like `0.3.1-dev (abc1234 2024-05-01, dirty)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.version, self.channel)?;
+
+        let location = match (self.commit_hash, self.commit_date) {
+            (Some(hash), Some(date)) => Some(format!("{} {}", hash, date)),
+            (Some(hash), None) => Some(hash.to_owned()),
+            (None, Some(date)) => Some(date.to_owned()),
+            (None, None) => None,
+        };
+        if let Some(location) = location {
+            let dirty = if self.worktree_clean == Some(false) { ", dirty" } else { "" };
+            write!(f, " ({}{})", location, dirty)?;
+        }
+        Ok(())
+    }
+}